@@ -1,18 +1,23 @@
-use bincode;
 use bzip2::read::BzDecoder;
-use log::{debug, error, info};
-use reqwest;
-use serde;
-use serde_yaml;
+use log::{debug, error, info, warn};
 use std::collections::HashMap;
 use std::io::{Read, Write};
 use tar::Archive;
 use xz2::read::XzDecoder;
 
+use crate::groups::{Group, ItemGroups, MarketGroup};
+use crate::history::MarketHistory;
+use crate::jumps::JumpMatrix;
 use crate::settings::SETTINGS;
-use crate::types::{Order, OrderGroup, Stargate, System, Type, Vector3};
+use crate::ships::{
+    ShipHolds, CAPACITY_ATTRIBUTE_ID, FLEET_HANGAR_CAPACITY_ATTRIBUTE_ID,
+    ORE_HOLD_CAPACITY_ATTRIBUTE_ID, SHIP_CATEGORY_ID,
+};
+use crate::types::{Order, OrderGroup, OrderRange, Stargate, StargateKind, System, Type, Vector3};
 use crate::urls;
 
+const UNPUBLISHED_HISTORY_DAYS: u32 = 2; // Recent days of market history that may be missing
+
 // {
 //         let mut settings = SETTINGS.lock().unwrap();
 //         settings.set_field1("new_value".to_string());
@@ -26,11 +31,17 @@ pub enum ESIError {
     InvalidData,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct ESI {
     pub orders: std::collections::HashMap<u32, OrderGroup>,
     pub systems: HashMap<u32, System>,
     pub types: HashMap<u32, Type>,
     pub mean_jump_distance: f64,
+    pub longest_jump_distance: f64,
+    pub jump_matrix: JumpMatrix,
+    pub history: MarketHistory,
+    pub item_groups: ItemGroups,
+    pub ship_holds: HashMap<u32, ShipHolds>, // Key: type id of the hull
 }
 
 impl ESI {
@@ -40,6 +51,11 @@ impl ESI {
             systems: HashMap::new(),
             types: HashMap::new(),
             mean_jump_distance: 0.0,
+            longest_jump_distance: 0.0,
+            jump_matrix: JumpMatrix::default(),
+            history: MarketHistory::default(),
+            item_groups: ItemGroups::default(),
+            ship_holds: HashMap::new(),
         }
     }
 
@@ -47,8 +63,44 @@ impl ESI {
         let settings = SETTINGS.lock().unwrap();
         let path_exists = std::path::Path::new(".cache/").exists();
 
-        if !path_exists || settings.get_update_universe_data() {
-            info!("Cache directory does not exist or updating universe data was explicitly requested by the user.");
+        // Cached universe data from an older version can't be read any more
+        let mut cached_universe = None;
+        if path_exists && !settings.get_update_universe_data() {
+            match (
+                ESI::load(".cache/systems.bin"),
+                ESI::load(".cache/types.bin"),
+                ESI::load(".cache/groups.bin"),
+                ESI::load(".cache/ships.bin"),
+            ) {
+                (Ok(systems), Ok(types), Ok(item_groups), Ok(ship_holds)) => {
+                    cached_universe = Some((systems, types, item_groups, ship_holds))
+                }
+                _ => info!("Cached systems and types could not be loaded, fetching..."),
+            }
+        }
+
+        if let Some((systems, types, item_groups, ship_holds)) = cached_universe {
+            info!("Using cached systems and types data.");
+
+            self.systems = systems;
+            self.types = types;
+            self.item_groups = item_groups;
+            self.ship_holds = ship_holds;
+
+            match ESI::load::<JumpMatrix>(".cache/jumps.bin") {
+                Ok(jump_matrix) if jump_matrix.is_valid_for(&self.systems) => {
+                    info!("Using cached jump counts.");
+
+                    self.jump_matrix = jump_matrix;
+                }
+                _ => {
+                    info!("Cached jump counts are missing or outdated, computing...");
+
+                    self.compute_jump_matrix()?;
+                }
+            }
+        } else {
+            info!("Cache directory does not exist, is outdated or updating universe data was explicitly requested by the user.");
 
             if let Err(err) = self.fetch_universe_data() {
                 error!("Failed to fetch universe data!");
@@ -57,16 +109,30 @@ impl ESI {
 
             self.fetch_systems()?;
             self.fetch_types()?;
+            self.fetch_item_groups()?;
+
+            // Containers and structures have holds too, only ships can be flown
+            let groups = &self.item_groups.groups;
+            let types = &self.types;
+            self.ship_holds.retain(|type_id, _| {
+                types
+                    .get(type_id)
+                    .and_then(|item_type| groups.get(&item_type.group_id))
+                    .is_some_and(|group| group.category_id == SHIP_CATEGORY_ID)
+            });
 
             ESI::save(&self.systems, ".cache/systems.bin")?;
             ESI::save(&self.types, ".cache/types.bin")?;
-        } else {
-            info!("Using cached systems and types data.");
+            ESI::save(&self.item_groups, ".cache/groups.bin")?;
+            ESI::save(&self.ship_holds, ".cache/ships.bin")?;
 
-            self.systems = ESI::load(".cache/systems.bin")?;
-            self.types = ESI::load(".cache/types.bin")?;
+            // Jump counts of the old universe are no good any more
+            self.compute_jump_matrix()?;
         }
 
+        // Only now, so that neither the cache nor the jump counts contain them
+        self.add_custom_edges(settings.get_custom_edges_path())?;
+
         let orders_path = std::path::Path::new(".cache/orders.bin");
         if !orders_path.exists() {
             info!("Cached orders were not found, fetching...");
@@ -85,24 +151,131 @@ impl ESI {
                 self.fetch_orders()?;
 
                 ESI::save(&self.orders, ".cache/orders.bin")?;
-            } else {
+            } else if let Ok(orders) = ESI::load(".cache/orders.bin") {
                 info!("Using cached orders data.");
 
-                self.orders = ESI::load(".cache/orders.bin")?;
+                self.orders = orders;
+            } else {
+                info!("Cached orders could not be loaded, fetching...");
+
+                self.fetch_orders()?;
+
+                ESI::save(&self.orders, ".cache/orders.bin")?;
             }
         }
 
+        // Without history the volume filters let everything through, which is easy to miss
+        let history_days = settings.get_history_days();
+        if history_days == 0
+            && (settings.get_min_daily_volume() > 0.0
+                || settings.get_max_daily_volume_share().is_some())
+        {
+            warn!("Daily volume limits need market history, set history_days to apply them");
+        }
+        self.get_market_history(history_days);
+
         self.mean_jump_distance = self.calculate_mean_jump_distance();
+        self.longest_jump_distance = self.calculate_longest_jump_distance();
+        debug!(
+            "Mean jump distance: {:.3e}m, longest: {:.3e}m",
+            self.mean_jump_distance, self.longest_jump_distance
+        );
 
         Ok(())
     }
 
+    // Wormholes, Thera connections and jump bridges the user knows about, usable both ways.
+    // The file is optional and expired connections are skipped.
+    fn add_custom_edges(&mut self, path: &str) -> Result<(), ESIError> {
+        if !std::path::Path::new(path).exists() {
+            debug!("No custom edges found. \n\tPath: {}", path);
+            return Ok(());
+        }
+
+        let data = std::fs::read_to_string(path).map_err(|err| {
+            error!(
+                "Failed to read custom edges! \n\tPath: {}\n\tError: {}",
+                path, err
+            );
+            ESIError::IoError(err)
+        })?;
+
+        let edges: Vec<CustomEdgeData> = if path.ends_with(".csv") {
+            csv::Reader::from_reader(data.as_bytes())
+                .deserialize()
+                .collect::<Result<_, _>>()
+                .map_err(|err| {
+                    error!("Failed to parse custom edges! \n\tError: {}", err);
+                    ESIError::InvalidData
+                })?
+        } else {
+            serde_yaml::from_str(&data).map_err(|err| {
+                error!("Failed to parse custom edges! \n\tError: {}", err);
+                ESIError::InvalidData
+            })?
+        };
+
+        let now = chrono::Utc::now().timestamp();
+        let mut added_edges = 0;
+        for edge in edges {
+            if let Some(expires) = &edge.expires {
+                match chrono::DateTime::parse_from_rfc3339(expires) {
+                    Ok(expires) if expires.timestamp() <= now => continue,
+                    Ok(_) => {}
+                    Err(err) => {
+                        warn!(
+                            "Invalid expiry of custom edge {} - {}: {}",
+                            edge.origin, edge.destination, err
+                        );
+                        continue;
+                    }
+                }
+            }
+
+            let (origin, destination) = match (
+                System::find_id(&self.systems, &edge.origin),
+                System::find_id(&self.systems, &edge.destination),
+            ) {
+                (Some(origin), Some(destination)) => (origin, destination),
+                _ => {
+                    warn!(
+                        "Unknown system in custom edge {} - {}",
+                        edge.origin, edge.destination
+                    );
+                    continue;
+                }
+            };
+
+            for (from, to) in [(origin, destination), (destination, origin)] {
+                let weight = Stargate::get_weight(self.systems[&to].security_status);
+                if let Some(system) = self.systems.get_mut(&from) {
+                    system.stargates.push(Stargate {
+                        origin: from,
+                        destination: to,
+                        weight,
+                        kind: edge.kind,
+                    });
+                }
+            }
+            added_edges += 1;
+        }
+
+        info!("Added {} custom edges", added_edges);
+
+        Ok(())
+    }
+
+    // Custom edges are left out, they don't follow the distances of the gate network
     fn calculate_mean_jump_distance(&mut self) -> f64 {
         let mut total_distance = 0.0;
         let mut total_jumps = 0;
 
         for system in self.systems.values() {
             for stargate in &system.stargates {
+                if stargate.kind != StargateKind::Stargate {
+                    continue;
+                }
+
                 let destination_system = self.systems.get(&stargate.destination).unwrap();
                 total_distance += system.position.distance(&destination_system.position);
                 total_jumps += 1;
@@ -112,6 +285,35 @@ impl ESI {
         total_distance / total_jumps as f64
     }
 
+    fn compute_jump_matrix(&mut self) -> Result<(), ESIError> {
+        let start = std::time::Instant::now();
+        self.jump_matrix = JumpMatrix::new(&self.systems);
+        debug!(
+            "Computing jump counts took {:?}ms",
+            start.elapsed().as_millis()
+        );
+
+        ESI::save(&self.jump_matrix, ".cache/jumps.bin")
+    }
+
+    fn calculate_longest_jump_distance(&mut self) -> f64 {
+        let mut longest_distance: f64 = 0.0;
+
+        for system in self.systems.values() {
+            for stargate in &system.stargates {
+                if stargate.kind != StargateKind::Stargate {
+                    continue;
+                }
+
+                let destination_system = self.systems.get(&stargate.destination).unwrap();
+                longest_distance =
+                    longest_distance.max(system.position.distance(&destination_system.position));
+            }
+        }
+
+        longest_distance
+    }
+
     fn fetch_universe_data(&mut self) -> Result<(), ESIError> {
         info!("Updating universe data...");
 
@@ -156,22 +358,49 @@ impl ESI {
                 ESIError::InvalidData
             })?;
 
-        let mut stargate_map = HashMap::new();
-        for (key, value) in stargates {
+        let mut stargate_map: HashMap<u32, Vec<u32>> = HashMap::new();
+        for value in stargates.into_values() {
             let system_id = value.system_id;
             let destination_system_id = value.destination.system_id;
 
             stargate_map
                 .entry(system_id)
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(destination_system_id);
         }
 
         Ok(stargate_map)
     }
 
+    // Key: constellation id, value: region id
+    fn get_constellation_regions() -> Result<HashMap<u32, u32>, ESIError> {
+        let data = std::fs::read_to_string(
+            ".cache/eve-ref-esi-scrape/data/tranquility/universe/constellations.en-us.yaml",
+        )
+        .map_err(|err| {
+            error!("Failed to read constellations data! \n\tError: {}", err);
+            ESIError::IoError(err)
+        })?;
+
+        let constellations: HashMap<String, ConstellationData> = serde_yaml::from_str(&data)
+            .map_err(|err| {
+                error!("Failed to parse constellations data! \n\tError: {}", err);
+                ESIError::InvalidData
+            })?;
+
+        let mut region_map = HashMap::new();
+        for (key, value) in constellations {
+            if let Ok(constellation_id) = key.parse::<u32>() {
+                region_map.insert(constellation_id, value.region_id);
+            }
+        }
+
+        Ok(region_map)
+    }
+
     fn fetch_systems(&mut self) -> Result<(), ESIError> {
         let stargates = ESI::get_stargates()?;
+        let constellation_regions = ESI::get_constellation_regions()?;
         let data = std::fs::read_to_string(
             ".cache/eve-ref-esi-scrape/data/tranquility/universe/systems.en-us.yaml",
         )
@@ -193,11 +422,7 @@ impl ESI {
             let name = &value.name;
             let security_status = value.security_status as f32;
 
-            let system_position = Vector3 {
-                x: value.position.x as f64,
-                y: value.position.y as f64,
-                z: value.position.z as f64,
-            };
+            let system_position = value.position.clone();
 
             let mut system_stargates = Vec::new();
             if let Some(stargate_destinations) = stargates.get(&system_id) {
@@ -213,14 +438,11 @@ impl ESI {
                         }
                     }
 
-                    let mut weight =
-                        1.0 + ((destination_security - (-1.0)) * (10.0 - 1.0) / (1.0 - (-1.0)));
-                    weight = ((10.0 + 1.0) - weight).ceil();
-
                     system_stargates.push(Stargate {
                         origin: system_id,
                         destination: stargate_destination,
-                        weight,
+                        weight: Stargate::get_weight(destination_security),
+                        kind: StargateKind::Stargate,
                     });
                 }
             }
@@ -233,6 +455,11 @@ impl ESI {
                     security_status,
                     stargates: system_stargates,
                     position: system_position,
+                    constellation_id: value.constellation_id,
+                    region_id: constellation_regions
+                        .get(&value.constellation_id)
+                        .copied()
+                        .unwrap_or_default(),
                 },
             );
         }
@@ -263,11 +490,32 @@ impl ESI {
             }
 
             let type_id = key.parse::<u32>().unwrap();
+            let get_attribute = |attribute_id: u32| {
+                value
+                    .dogma_attributes
+                    .iter()
+                    .find(|attribute| attribute.attribute_id == attribute_id)
+                    .map_or(0.0, |attribute| attribute.value as f32)
+            };
+            let holds = ShipHolds {
+                cargo: value
+                    .capacity
+                    .map_or(get_attribute(CAPACITY_ATTRIBUTE_ID), |capacity| {
+                        capacity as f32
+                    }),
+                fleet_hangar: get_attribute(FLEET_HANGAR_CAPACITY_ATTRIBUTE_ID),
+                ore_hold: get_attribute(ORE_HOLD_CAPACITY_ATTRIBUTE_ID),
+            };
+            if holds.cargo > 0.0 || holds.fleet_hangar > 0.0 || holds.ore_hold > 0.0 {
+                self.ship_holds.insert(type_id, holds);
+            }
+
             self.types.insert(
                 type_id,
                 Type {
                     type_id,
                     group_id: value.group_id,
+                    market_group_id: value.market_group_id,
                     name: value.name,
                     volume: value.packaged_volume as f32,
                 },
@@ -277,6 +525,70 @@ impl ESI {
         Ok(())
     }
 
+    // Inventory groups with their categories, and the market browser's tree of groups
+    fn fetch_item_groups(&mut self) -> Result<(), ESIError> {
+        let read = |path: &str| {
+            std::fs::read_to_string(format!(
+                ".cache/eve-ref-esi-scrape/data/tranquility/{}",
+                path
+            ))
+            .map_err(|err| {
+                error!(
+                    "Failed to read group data! \n\tPath: {}\n\tError: {}",
+                    path, err
+                );
+                ESIError::IoError(err)
+            })
+        };
+
+        let groups: HashMap<String, GroupData> =
+            serde_yaml::from_str(&read("universe/groups.en-us.yaml")?).map_err(|err| {
+                error!("Failed to parse groups data! \n\tError: {}", err);
+                ESIError::InvalidData
+            })?;
+        let categories: HashMap<String, CategoryData> =
+            serde_yaml::from_str(&read("universe/categories.en-us.yaml")?).map_err(|err| {
+                error!("Failed to parse categories data! \n\tError: {}", err);
+                ESIError::InvalidData
+            })?;
+        let market_groups: HashMap<String, MarketGroupData> =
+            serde_yaml::from_str(&read("markets/groups.en-us.yaml")?).map_err(|err| {
+                error!("Failed to parse market groups data! \n\tError: {}", err);
+                ESIError::InvalidData
+            })?;
+
+        info!("Parsing group data...");
+        for (key, value) in groups {
+            if let Ok(group_id) = key.parse::<u32>() {
+                self.item_groups.groups.insert(
+                    group_id,
+                    Group {
+                        name: value.name,
+                        category_id: value.category_id,
+                    },
+                );
+            }
+        }
+        for (key, value) in categories {
+            if let Ok(category_id) = key.parse::<u32>() {
+                self.item_groups.categories.insert(category_id, value.name);
+            }
+        }
+        for (key, value) in market_groups {
+            if let Ok(market_group_id) = key.parse::<u32>() {
+                self.item_groups.market_groups.insert(
+                    market_group_id,
+                    MarketGroup {
+                        name: value.name,
+                        parent_id: value.parent_group_id,
+                    },
+                );
+            }
+        }
+
+        Ok(())
+    }
+
     fn fetch_orders(&mut self) -> Result<(), ESIError> {
         let response = reqwest::blocking::get(urls::get_market_data_url()).map_err(|err| {
             error!("Failed to perform an API call! \n\tError: {}", err);
@@ -311,24 +623,32 @@ impl ESI {
                 None => continue,
             };
 
+            let issued = record
+                .get(2)
+                .and_then(|issued| chrono::DateTime::parse_from_rfc3339(issued).ok())
+                .map_or(0, |issued| issued.timestamp());
+
             let order = Order {
+                order_id: record.get(5).unwrap_or("0").parse().unwrap_or(0),
                 is_buy_order: record.get(1).unwrap_or("false").parse().unwrap_or(false),
                 price: record.get(6).unwrap_or("0.0").parse().unwrap_or(0.0),
                 station_id: record.get(13).unwrap_or("0").parse().unwrap_or(0),
                 system_id: record.get(8).unwrap_or("0").parse().unwrap_or(0),
                 region_id: record.get(14).unwrap_or("0").parse().unwrap_or(0),
                 volume: record.get(10).unwrap_or("0.0").parse().unwrap_or(0.0),
+                min_volume: record.get(4).unwrap_or("1.0").parse().unwrap_or(1.0),
+                range: OrderRange::parse(record.get(7).unwrap_or("station")),
+                issued,
+                duration: record.get(0).unwrap_or("0").parse().unwrap_or(0),
                 order_type: order_type.clone(),
+                outlier: None,
             };
 
             if order.station_id == 0 {
                 continue;
             }
 
-            self.orders
-                .entry(type_id)
-                .or_insert_with(OrderGroup::new)
-                .add_order(order);
+            self.orders.entry(type_id).or_default().add_order(order);
         }
 
         info!("orders total: {}", i);
@@ -336,6 +656,100 @@ impl ESI {
         Ok(())
     }
 
+    // History only changes once a day. Trading works without it, so failing to get it is no
+    // reason to stop.
+    fn get_market_history(&mut self, days: u32) {
+        if days == 0 {
+            return;
+        }
+
+        let one_day_ago = chrono::Utc::now().timestamp() - 24 * 60 * 60;
+        if std::path::Path::new(".cache/history.bin").exists() {
+            match ESI::load::<MarketHistory>(".cache/history.bin") {
+                // Days that weren't published yet don't make the cache any less complete
+                Ok(history) if history.requested_days == days && history.fetched > one_day_ago => {
+                    info!("Using cached market history.");
+
+                    self.history = history;
+                    return;
+                }
+                _ => info!("Market history is outdated, updating..."),
+            }
+        } else {
+            info!("Cached market history was not found, fetching...");
+        }
+
+        if let Err(err) = self.fetch_market_history(days) {
+            warn!("Continuing without market history: {}", err);
+            self.history = MarketHistory::default();
+            return;
+        }
+
+        if let Err(err) = ESI::save(&self.history, ".cache/history.bin") {
+            warn!("Market history could not be cached: {}", err);
+        }
+    }
+
+    // One file per day, the latest one is from yesterday at best and may take another day.
+    // Days that aren't published yet are skipped, so the average isn't diluted by them.
+    fn fetch_market_history(&mut self, days: u32) -> Result<(), ESIError> {
+        let today = chrono::Utc::now().date_naive();
+        let mut history = MarketHistory::new(days, chrono::Utc::now().timestamp());
+
+        for offset in 1..=days {
+            let day = today - chrono::Days::new(offset as u64);
+            let response = match reqwest::blocking::get(urls::get_market_history_url(day))
+                .and_then(|response| response.error_for_status())
+            {
+                Ok(response) => response,
+                Err(err) if history.days == 0 && offset <= UNPUBLISHED_HISTORY_DAYS => {
+                    debug!("No market history for {} yet \n\tError: {}", day, err);
+                    continue;
+                }
+                Err(err) => {
+                    error!("Failed to perform an API call! \n\tError: {}", err);
+                    return Err(ESIError::RequestError);
+                }
+            };
+
+            let mut decompressor = BzDecoder::new(response);
+            let mut data_buffer = Vec::new();
+            decompressor.read_to_end(&mut data_buffer).map_err(|err| {
+                error!("Failed to read decompressed data! \n\tError: {}", err);
+                ESIError::InvalidData
+            })?;
+
+            info!("Parsing market history of {}...", day);
+            let mut reader = csv::Reader::from_reader(std::io::Cursor::new(data_buffer));
+            for result in reader.deserialize() {
+                let record: HistoryData = result.map_err(|err| {
+                    error!("Failed to parse market history! \n\tError: {}", err);
+                    ESIError::InvalidData
+                })?;
+
+                history.add_day(
+                    record.region_id,
+                    record.type_id,
+                    record.average,
+                    record.highest,
+                    record.lowest,
+                    record.volume,
+                );
+            }
+            history.days += 1;
+        }
+
+        if history.days == 0 {
+            error!("No market history could be fetched!");
+            return Err(ESIError::RequestError);
+        }
+
+        info!("Fetched {} days of market history", history.days);
+        self.history = history;
+
+        Ok(())
+    }
+
     pub fn save<T: serde::Serialize>(data: &T, path: &str) -> Result<(), ESIError> {
         debug!("Trying to save... \n\tPath: {}", path);
 
@@ -402,12 +816,38 @@ impl From<std::io::Error> for ESIError {
     }
 }
 
+// One row of the everef market history, a single day of one type in one region
+#[derive(Debug, serde::Deserialize)]
+struct HistoryData {
+    average: f32,
+    highest: f32,
+    lowest: f32,
+    volume: f64,
+    region_id: u32,
+    type_id: u32,
+}
+
 // These are required for easier serde_yaml deserialization.
 #[derive(Debug, serde::Deserialize)]
 struct SystemData {
     name: String,
     security_status: f64,
     position: Vector3,
+    constellation_id: u32,
+}
+
+// One line of the custom edges file, systems by id or name and expiry as RFC 3339
+#[derive(Debug, serde::Deserialize)]
+struct CustomEdgeData {
+    origin: String,
+    destination: String,
+    kind: StargateKind,
+    expires: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ConstellationData {
+    region_id: u32,
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -421,10 +861,37 @@ struct StargateData {
     system_id: u32,
 }
 
+#[derive(Debug, serde::Deserialize)]
+struct GroupData {
+    name: String,
+    category_id: u32,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct CategoryData {
+    name: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct MarketGroupData {
+    name: String,
+    parent_group_id: Option<u32>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct DogmaAttributeData {
+    attribute_id: u32,
+    value: f64,
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct TypeData {
     group_id: u32,
+    market_group_id: Option<u32>,
     name: String,
     packaged_volume: f64,
     published: bool,
+    capacity: Option<f64>,
+    #[serde(default)]
+    dogma_attributes: Vec<DogmaAttributeData>,
 }
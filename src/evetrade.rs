@@ -1,11 +1,20 @@
 use chrono::Local;
 use env_logger::Builder;
-use log::{error, info, Level, LevelFilter};
+use log::{error, info, Level};
 use std::io::Write;
 
 use crate::esi;
 use crate::processor::OrderProcessor;
 use crate::route::Route;
+use crate::settings::SETTINGS;
+use crate::types::TradePair;
+
+// Chained trade routes, or a flat table of single station to station hauls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    Routes,
+    StationHauling,
+}
 
 #[derive(Debug)]
 pub enum EvetradeError {
@@ -17,6 +26,8 @@ pub struct Evetrade {
     esi: esi::ESI,
     is_initialized: bool,
     routes: Vec<Route>,
+    trade_pairs: Vec<TradePair>,
+    output_mode: OutputMode,
 }
 
 impl Evetrade {
@@ -25,10 +36,14 @@ impl Evetrade {
             is_initialized: false,
             esi: esi::ESI::new(),
             routes: Vec::new(),
+            trade_pairs: Vec::new(),
+            output_mode: OutputMode::Routes,
         }
     }
 
     pub fn init(&mut self) -> Result<(), EvetradeError> {
+        let log_level = SETTINGS.lock().unwrap().get_level();
+
         Builder::new()
             .format(|buf, record| {
                 let now = Local::now();
@@ -74,12 +89,12 @@ impl Evetrade {
                     record.args()
                 )
             })
-            .filter_level(LevelFilter::Debug)
+            .filter_level(log_level.to_level_filter())
             .init();
 
         info!("Logger initialized successfully!");
 
-        if let Err(_) = self.esi.get_all_data() {
+        if self.esi.get_all_data().is_err() {
             error!("Failed to fetch all required data! Shutting down...");
             return Err(EvetradeError::ESIError);
         }
@@ -89,23 +104,52 @@ impl Evetrade {
     }
 
     pub fn compute(&mut self) -> Result<(), EvetradeError> {
-        info!("Computing routes...");
+        let settings = SETTINGS.lock().unwrap();
+        self.output_mode = settings.get_output_mode();
+        let hauling_sort = settings.get_hauling_sort();
+        let hauling_limit = settings.get_hauling_limit();
+        let min_profit = settings.get_min_profit();
+        drop(settings);
+
         let mut processor = OrderProcessor::new(
             &mut self.esi.orders,
             &self.esi.systems,
             &self.esi.types,
-            self.esi.mean_jump_distance,
+            self.esi.longest_jump_distance,
+            &self.esi.jump_matrix,
+            &self.esi.history,
+            &self.esi.item_groups,
+            &self.esi.ship_holds,
         );
 
-        self.routes = processor.compute();
-
-        info!("Sorting routes...");
-        Route::sort_routes(&mut self.routes);
+        match self.output_mode {
+            OutputMode::Routes => {
+                info!("Computing routes...");
+                self.routes = processor.compute();
+
+                info!("Sorting routes...");
+                Route::sort_routes(&mut self.routes);
+            }
+            OutputMode::StationHauling => {
+                info!("Computing station hauls...");
+                self.trade_pairs = processor.compute_hauling();
+
+                info!("Sorting station hauls...");
+                self.trade_pairs
+                    .retain(|pair| pair.potential_profit >= min_profit);
+                TradePair::sort_pairs(&mut self.trade_pairs, hauling_sort);
+                self.trade_pairs.truncate(hauling_limit);
+            }
+        }
 
         Ok(())
     }
 
     pub fn display_and_save(&mut self) -> Result<(), EvetradeError> {
+        if self.output_mode == OutputMode::StationHauling {
+            return self.display_and_save_hauling();
+        }
+
         info!("Displaying routes...");
 
         for route in &mut self.routes {
@@ -128,6 +172,26 @@ impl Evetrade {
 
         Ok(())
     }
+
+    fn display_and_save_hauling(&self) -> Result<(), EvetradeError> {
+        info!("Displaying station hauls...");
+
+        let mut table = TradePair::represent_header();
+        for pair in &self.trade_pairs {
+            table.push_str(&pair.represent(&self.esi.systems));
+            table.push('\n');
+        }
+        println!("{}", table);
+
+        info!("Saving station hauls...");
+
+        std::fs::write("results.txt", table).map_err(|err| {
+            error!("Failed to write to file: {}", err);
+            EvetradeError::IOError
+        })?;
+
+        Ok(())
+    }
 }
 
 impl std::fmt::Display for EvetradeError {
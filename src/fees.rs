@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+use crate::settings::Settings;
+use crate::sizer::get_profit_percentage;
+use crate::types::Order;
+
+const BASE_SALES_TAX: f32 = 7.5;
+const SALES_TAX_REDUCTION_PER_ACCOUNTING_LEVEL: f32 = 0.11;
+const BASE_BROKER_FEE: f32 = 3.0;
+const BROKER_FEE_REDUCTION_PER_BROKER_RELATIONS_LEVEL: f32 = 0.3;
+const BROKER_FEE_REDUCTION_PER_FACTION_STANDING: f32 = 0.03;
+const BROKER_FEE_REDUCTION_PER_CORPORATION_STANDING: f32 = 0.02;
+
+// Transaction costs, all rates are percentages.
+// Taking an existing order only costs sales tax when selling, placing an order adds the broker fee.
+#[derive(Clone, Default)]
+pub struct FeeModel {
+    sales_tax: f32,
+    broker_fee: f32,
+    structure_broker_fees: HashMap<u32, f32>, // Key: station id
+    buy_with_orders: bool,
+    sell_with_orders: bool,
+}
+
+impl FeeModel {
+    pub fn new(settings: &Settings) -> Self {
+        let accounting_level = settings.get_accounting_level().min(5) as f32;
+        let broker_relations_level = settings.get_broker_relations_level().min(5) as f32;
+
+        let sales_tax =
+            BASE_SALES_TAX * (1.0 - SALES_TAX_REDUCTION_PER_ACCOUNTING_LEVEL * accounting_level);
+        let broker_fee = (BASE_BROKER_FEE
+            - BROKER_FEE_REDUCTION_PER_BROKER_RELATIONS_LEVEL * broker_relations_level
+            - BROKER_FEE_REDUCTION_PER_FACTION_STANDING * settings.get_faction_standing()
+            - BROKER_FEE_REDUCTION_PER_CORPORATION_STANDING * settings.get_corporation_standing())
+        .max(0.0);
+
+        FeeModel {
+            sales_tax,
+            broker_fee,
+            structure_broker_fees: settings.get_structure_broker_fees().clone(),
+            buy_with_orders: settings.get_buy_with_orders(),
+            sell_with_orders: settings.get_sell_with_orders(),
+        }
+    }
+
+    // Player structures set their own broker fee, NPC stations use the skill and standing based one
+    pub fn get_broker_fee(&self, station_id: u32) -> f32 {
+        self.structure_broker_fees
+            .get(&station_id)
+            .copied()
+            .unwrap_or(self.broker_fee)
+    }
+
+    // What one unit bought from this sell order actually costs us
+    pub fn get_net_buy_price(&self, sell_order: &Order) -> f32 {
+        let mut fee = 0.0;
+        if self.buy_with_orders {
+            fee += self.get_broker_fee(sell_order.station_id);
+        }
+
+        sell_order.price * (1.0 + fee / 100.0)
+    }
+
+    // What one unit sold into this buy order actually earns us
+    pub fn get_net_sell_price(&self, buy_order: &Order) -> f32 {
+        let mut fee = self.sales_tax;
+        if self.sell_with_orders {
+            fee += self.get_broker_fee(buy_order.station_id);
+        }
+
+        buy_order.price * (1.0 - fee / 100.0)
+    }
+
+    // Margin between taking a sell order here and filling a buy order there, after fees
+    pub fn get_profit_percentage(&self, sell_order: &Order, buy_order: &Order) -> f32 {
+        get_profit_percentage(
+            self.get_net_buy_price(sell_order),
+            self.get_net_sell_price(buy_order),
+        )
+    }
+}
@@ -0,0 +1,140 @@
+use log::{info, warn};
+use std::collections::HashMap;
+
+use crate::fees::FeeModel;
+use crate::jumps::JumpMatrix;
+use crate::pathfinder::get_light_years;
+use crate::settings::Settings;
+use crate::types::{OrderGroup, System};
+
+const FUEL_REDUCTION_PER_JUMP_FUEL_CONSERVATION_LEVEL: f32 = 10.0;
+const FUEL_SEARCH_JUMPS: usize = 5; // How far from the origin we fly to buy isotopes
+
+// Every jump drive burns the isotope of its race
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuelType {
+    Helium,
+    Hydrogen,
+    Nitrogen,
+    Oxygen,
+}
+
+impl FuelType {
+    pub fn get_type_id(&self) -> u32 {
+        match self {
+            FuelType::Helium => 16274,
+            FuelType::Hydrogen => 17889,
+            FuelType::Nitrogen => 17888,
+            FuelType::Oxygen => 17887,
+        }
+    }
+}
+
+impl std::fmt::Display for FuelType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FuelType::Helium => write!(f, "Helium Isotopes"),
+            FuelType::Hydrogen => write!(f, "Hydrogen Isotopes"),
+            FuelType::Nitrogen => write!(f, "Nitrogen Isotopes"),
+            FuelType::Oxygen => write!(f, "Oxygen Isotopes"),
+        }
+    }
+}
+
+// What a ship burns per light year before skills
+#[derive(Debug, Clone, Copy)]
+pub struct FuelProfile {
+    pub fuel_type: FuelType,
+    pub consumption_per_light_year: f32, // Units
+    pub jump_fuel_conservation_level: u8,
+}
+
+// Fuel bill of cyno jumps, priced at the cheapest isotopes close to where a route starts.
+// Prices include the fees of buying them.
+pub struct FuelModel<'a> {
+    fuel_type: FuelType,
+    consumption_per_light_year: f32, // Units, after skills
+    prices: Vec<(u32, f32)>,         // System id and price of every sell order, cheapest first
+    jump_matrix: &'a JumpMatrix,
+}
+
+impl<'a> FuelModel<'a> {
+    // Only jump drive routes burn fuel, None without a jump drive or fuel profile.
+    // Has to run before preprocessing, which drops the isotope orders along with the other
+    // types that can't be traded at a profit.
+    pub fn new(
+        settings: &Settings,
+        orders: &HashMap<u32, OrderGroup>,
+        fees: &FeeModel,
+        jump_matrix: &'a JumpMatrix,
+    ) -> Option<Self> {
+        settings.get_jump_drive()?;
+        let profile = settings.get_fuel_profile()?;
+
+        let reduction = FUEL_REDUCTION_PER_JUMP_FUEL_CONSERVATION_LEVEL
+            * profile.jump_fuel_conservation_level.min(5) as f32;
+        let mut prices: Vec<(u32, f32)> = orders
+            .get(&profile.fuel_type.get_type_id())
+            .map_or(&[][..], |order_group| &order_group.sell)
+            .iter()
+            .map(|order| (order.system_id, fees.get_net_buy_price(order)))
+            .collect();
+        prices.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+
+        if prices.is_empty() {
+            warn!(
+                "No sell orders for {}, jump fuel can't be priced",
+                profile.fuel_type
+            );
+        } else {
+            info!(
+                "Pricing jump fuel from {} sell orders for {}",
+                prices.len(),
+                profile.fuel_type
+            );
+        }
+
+        Some(FuelModel {
+            fuel_type: profile.fuel_type,
+            consumption_per_light_year: profile.consumption_per_light_year
+                * (1.0 - reduction / 100.0),
+            prices,
+            jump_matrix,
+        })
+    }
+
+    pub fn get_fuel_type(&self) -> FuelType {
+        self.fuel_type
+    }
+
+    // Cheapest isotopes within a few jumps of the origin, the cheapest anywhere otherwise
+    pub fn get_price(&self, origin: u32) -> f32 {
+        self.prices
+            .iter()
+            .find(|(system_id, _)| {
+                self.jump_matrix
+                    .get_jumps(origin, *system_id)
+                    .is_some_and(|jumps| jumps <= FUEL_SEARCH_JUMPS)
+            })
+            .or(self.prices.first())
+            .map_or(0.0, |(_, price)| *price)
+    }
+
+    // Every jump burns whole units
+    pub fn get_jump_fuel(&self, from: &System, to: &System) -> f32 {
+        let light_years = get_light_years(from.position.distance(&to.position)) as f32;
+        (light_years * self.consumption_per_light_year).ceil()
+    }
+
+    pub fn get_path_fuel(&self, path: &[System]) -> f32 {
+        path.windows(2)
+            .map(|pair| self.get_jump_fuel(&pair[0], &pair[1]))
+            .sum()
+    }
+
+    // What flying the path costs when the fuel was bought around the origin
+    pub fn get_path_cost(&self, origin: u32, path: &[System]) -> f32 {
+        self.get_path_fuel(path) * self.get_price(origin)
+    }
+}
@@ -0,0 +1,107 @@
+use log::warn;
+use std::collections::{HashMap, HashSet};
+
+use crate::types::Type;
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct Group {
+    pub name: String,
+    pub category_id: u32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct MarketGroup {
+    pub name: String,
+    pub parent_id: Option<u32>,
+}
+
+// Where every type belongs, both in the inventory and in the market browser
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+pub struct ItemGroups {
+    pub groups: HashMap<u32, Group>,              // Key: group id
+    pub categories: HashMap<u32, String>,         // Key: category id, value: name
+    pub market_groups: HashMap<u32, MarketGroup>, // Key: market group id
+}
+
+// Categories, groups and market groups picked by name. Market groups cover all of their
+// subgroups, so "Ship SKINs" catches every SKIN whatever ship it is for.
+#[derive(Default)]
+pub struct ItemFilter {
+    categories: HashSet<u32>,
+    groups: HashSet<u32>,
+    market_groups: HashSet<u32>,
+}
+
+impl ItemGroups {
+    // Names are case insensitive and may match on any of the three levels
+    pub fn resolve_filter(&self, names: &[String]) -> ItemFilter {
+        let mut filter = ItemFilter::default();
+
+        for name in names {
+            let is_match = |other: &str| other.eq_ignore_ascii_case(name);
+            let categories: Vec<u32> = self
+                .categories
+                .iter()
+                .filter(|(_, category)| is_match(category))
+                .map(|(&category_id, _)| category_id)
+                .collect();
+            let groups: Vec<u32> = self
+                .groups
+                .iter()
+                .filter(|(_, group)| is_match(&group.name))
+                .map(|(&group_id, _)| group_id)
+                .collect();
+            let market_groups: Vec<u32> = self
+                .market_groups
+                .iter()
+                .filter(|(_, market_group)| is_match(&market_group.name))
+                .map(|(&market_group_id, _)| market_group_id)
+                .collect();
+
+            if categories.is_empty() && groups.is_empty() && market_groups.is_empty() {
+                warn!("Unknown category, group or market group: {}", name);
+            }
+
+            filter.categories.extend(categories);
+            filter.groups.extend(groups);
+            filter.market_groups.extend(market_groups);
+        }
+
+        filter
+    }
+
+    // Whether the type is in any of the filter's categories, groups or market groups
+    pub fn matches(&self, filter: &ItemFilter, item_type: &Type) -> bool {
+        if filter.groups.contains(&item_type.group_id) {
+            return true;
+        }
+
+        let category_id = self
+            .groups
+            .get(&item_type.group_id)
+            .map(|group| group.category_id);
+        if category_id.is_some_and(|category_id| filter.categories.contains(&category_id)) {
+            return true;
+        }
+
+        // Market groups are a tree, a type belongs to all of its ancestors too
+        let mut market_group_id = item_type.market_group_id;
+        while let Some(id) = market_group_id {
+            if filter.market_groups.contains(&id) {
+                return true;
+            }
+            market_group_id = self
+                .market_groups
+                .get(&id)
+                .and_then(|market_group| market_group.parent_id);
+        }
+
+        false
+    }
+}
+
+impl ItemFilter {
+    pub fn is_empty(&self) -> bool {
+        self.categories.is_empty() && self.groups.is_empty() && self.market_groups.is_empty()
+    }
+}
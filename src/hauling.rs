@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::types::{System, TradePair};
+
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HaulingSort {
+    Profit,
+    ProfitPerJump,
+    Roi,
+    Jumps,
+}
+
+impl TradePair {
+    // Ties are broken on the type and stations, so that equal rows keep their order between runs
+    pub fn sort_pairs(pairs: &mut [TradePair], sort: HaulingSort) {
+        pairs.sort_by(|a, b| {
+            match sort {
+                HaulingSort::Profit => b.potential_profit.total_cmp(&a.potential_profit),
+                HaulingSort::ProfitPerJump => b.profit_per_jump.total_cmp(&a.profit_per_jump),
+                HaulingSort::Roi => b.roi.total_cmp(&a.roi),
+                HaulingSort::Jumps => a
+                    .jumps
+                    .cmp(&b.jumps)
+                    .then(b.potential_profit.total_cmp(&a.potential_profit)),
+            }
+            .then(a.order_type.type_id.cmp(&b.order_type.type_id))
+            .then(a.buy_station_id.cmp(&b.buy_station_id))
+            .then(a.sell_station_id.cmp(&b.sell_station_id))
+        });
+    }
+
+    pub fn represent_header() -> String {
+        let mut representation = String::new();
+
+        writeln!(
+            representation,
+            "{:<40} {:<32} {:<32} {:>10} {:>14} {:>14} {:>16} {:>6} {:>16} {:>8} {:>12}",
+            "Item",
+            "From",
+            "To",
+            "Units",
+            "Buy price",
+            "Sell price",
+            "Profit",
+            "Jumps",
+            "Profit per jump",
+            "ROI",
+            "Days to sell"
+        )
+        .unwrap();
+
+        representation
+    }
+
+    // Stations are shown by id next to their system, we have no station names
+    pub fn represent(&self, systems: &HashMap<u32, System>) -> String {
+        let get_location = |station_id: u32, system_id: u32| match systems.get(&system_id) {
+            Some(system) => format!(
+                "{} ({:.1}) @ {}",
+                system.name,
+                system.get_display_security(),
+                station_id
+            ),
+            None => station_id.to_string(),
+        };
+
+        // Nothing sold in the region lately means there is no telling when it will
+        let days_to_sell = match self.days_to_sell {
+            Some(days) if days.is_finite() => format!("{:.1}", days),
+            Some(_) => "never".to_string(),
+            None => "-".to_string(),
+        };
+
+        format!(
+            "{:<40} {:<32} {:<32} {:>10} {:>14.2} {:>14.2} {:>16.2} {:>6} {:>16.2} {:>7.2}% {:>12}",
+            self.order_type.name,
+            get_location(self.buy_station_id, self.buy_system_id),
+            get_location(self.sell_station_id, self.sell_system_id),
+            self.volume,
+            self.buy_price,
+            self.sell_price,
+            self.potential_profit,
+            self.jumps,
+            self.profit_per_jump,
+            self.roi,
+            days_to_sell
+        )
+    }
+}
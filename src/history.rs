@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+// Daily market history of one type in one region, summed over the days fetched
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default)]
+pub struct HistoryStats {
+    pub volume: f64, // Units
+    pub value: f64,  // ISK, daily average price times volume
+    pub highest: f32,
+    pub lowest: f32,
+    pub days_traded: u32,
+}
+
+// What actually sold over the last days, the order book alone can't tell a market from a trap.
+// Types that never traded in a region have no entry, which counts as no volume at all.
+#[derive(serde::Serialize, serde::Deserialize, Default)]
+pub struct MarketHistory {
+    pub requested_days: u32, // Days asked for, the latest may not have been published yet
+    pub days: u32,           // Days fetched, including the ones nothing traded on
+    pub fetched: i64,        // Unix timestamp
+    stats: HashMap<(u32, u32), HistoryStats>, // Key: region id and type id
+}
+
+impl MarketHistory {
+    pub fn new(requested_days: u32, fetched: i64) -> Self {
+        MarketHistory {
+            requested_days,
+            days: 0,
+            fetched,
+            stats: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stats.is_empty()
+    }
+
+    pub fn add_day(
+        &mut self,
+        region_id: u32,
+        type_id: u32,
+        average: f32,
+        highest: f32,
+        lowest: f32,
+        volume: f64,
+    ) {
+        let stats = self.stats.entry((region_id, type_id)).or_default();
+        if stats.days_traded == 0 {
+            stats.highest = highest;
+            stats.lowest = lowest;
+        } else {
+            stats.highest = stats.highest.max(highest);
+            stats.lowest = stats.lowest.min(lowest);
+        }
+        stats.volume += volume;
+        stats.value += average as f64 * volume;
+        stats.days_traded += 1;
+    }
+
+    pub fn get_stats(&self, region_id: u32, type_id: u32) -> Option<&HistoryStats> {
+        self.stats.get(&(region_id, type_id))
+    }
+
+    // Units sold on an average day, None without any history to go by
+    pub fn get_daily_volume(&self, region_id: u32, type_id: u32) -> Option<f32> {
+        if self.is_empty() || self.days == 0 {
+            return None;
+        }
+
+        let volume = self
+            .get_stats(region_id, type_id)
+            .map_or(0.0, |stats| stats.volume);
+        Some((volume / self.days as f64) as f32)
+    }
+
+    // Volume weighted, None if the type didn't trade in the region at all
+    pub fn get_average_price(&self, region_id: u32, type_id: u32) -> Option<f32> {
+        self.get_stats(region_id, type_id)
+            .filter(|stats| stats.volume > 0.0)
+            .map(|stats| (stats.value / stats.volume) as f32)
+    }
+}
@@ -0,0 +1,103 @@
+use rayon::prelude::*;
+use std::collections::{HashMap, VecDeque};
+
+use crate::types::{StargateKind, System};
+
+const UNREACHABLE: u8 = u8::MAX;
+
+// Plain gate jumps between every two systems of the gate network, computed once per universe.
+// Custom edges are left out, order ranges don't follow them and they come and go.
+// No route preferences or security restrictions apply, which makes it exact for order ranges
+// and a lower bound for everything else.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+pub struct JumpMatrix {
+    index: HashMap<u32, usize>, // Key: system id, value: row and column
+    jumps: Vec<u8>,             // Row major, UNREACHABLE if there is no path
+}
+
+impl JumpMatrix {
+    // One BFS per system, systems without gates are left out
+    pub fn new(systems: &HashMap<u32, System>) -> Self {
+        let mut system_ids: Vec<u32> = systems
+            .values()
+            .filter(|system| Self::has_stargates(system))
+            .map(|system| system.id)
+            .collect();
+        system_ids.sort_unstable();
+
+        let index: HashMap<u32, usize> = system_ids
+            .iter()
+            .enumerate()
+            .map(|(i, &system_id)| (system_id, i))
+            .collect();
+        let size = system_ids.len();
+
+        let jumps = system_ids
+            .par_iter()
+            .flat_map_iter(|&origin| {
+                let mut row = vec![UNREACHABLE; size];
+                row[index[&origin]] = 0;
+                let mut queue = VecDeque::from([origin]);
+
+                while let Some(system_id) = queue.pop_front() {
+                    let jumps = row[index[&system_id]];
+                    for stargate in &systems[&system_id].stargates {
+                        if stargate.kind != StargateKind::Stargate {
+                            continue;
+                        }
+
+                        let next = match index.get(&stargate.destination) {
+                            Some(&next) => next,
+                            None => continue,
+                        };
+
+                        if row[next] == UNREACHABLE && jumps + 1 < UNREACHABLE {
+                            row[next] = jumps + 1;
+                            queue.push_back(stargate.destination);
+                        }
+                    }
+                }
+
+                row
+            })
+            .collect();
+
+        JumpMatrix { index, jumps }
+    }
+
+    // Whether the matrix was computed for these systems, a stale cache has to be recomputed
+    pub fn is_valid_for(&self, systems: &HashMap<u32, System>) -> bool {
+        let gate_systems = systems
+            .values()
+            .filter(|system| Self::has_stargates(system))
+            .count();
+
+        gate_systems == self.index.len()
+            && self.jumps.len() == self.index.len() * self.index.len()
+            && self
+                .index
+                .keys()
+                .all(|system_id| systems.contains_key(system_id))
+    }
+
+    fn has_stargates(system: &System) -> bool {
+        system
+            .stargates
+            .iter()
+            .any(|stargate| stargate.kind == StargateKind::Stargate)
+    }
+
+    // None if either system is off the gate network or there is no way between them
+    pub fn get_jumps(&self, from: u32, to: u32) -> Option<usize> {
+        if from == to {
+            return Some(0);
+        }
+
+        let from = *self.index.get(&from)?;
+        let to = *self.index.get(&to)?;
+        match self.jumps[from * self.index.len() + to] {
+            UNREACHABLE => None,
+            jumps => Some(jumps as usize),
+        }
+    }
+}
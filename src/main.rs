@@ -1,8 +1,18 @@
 mod esi;
 mod evetrade;
+mod fees;
+mod fuel;
+mod groups;
+mod hauling;
+mod history;
+mod jumps;
+mod outliers;
+mod pathfinder;
 mod processor;
 mod route;
 mod settings;
+mod ships;
+mod sizer;
 mod types;
 mod urls;
 
@@ -11,9 +21,39 @@ extern crate lazy_static;
 
 use log::{error, info};
 
-use evetrade::Evetrade;
+use evetrade::{Evetrade, OutputMode};
+use settings::SETTINGS;
 
 fn main() {
+    // The ship and the output change from run to run, everything else stays in the settings
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--hauling" {
+            SETTINGS
+                .lock()
+                .unwrap()
+                .set_output_mode(OutputMode::StationHauling);
+        } else if arg == "--ship" {
+            if let Some(profile) = args.next() {
+                let mut settings = SETTINGS.lock().unwrap();
+                let names: Vec<&str> = settings
+                    .get_ship_profiles()
+                    .iter()
+                    .map(|profile| profile.name.as_str())
+                    .collect();
+                if !names.iter().any(|name| name.eq_ignore_ascii_case(&profile)) {
+                    eprintln!(
+                        "Unknown ship profile: {}, known ones are: {}",
+                        profile,
+                        names.join(", ")
+                    );
+                    return;
+                }
+                settings.set_active_ship_profile(profile);
+            }
+        }
+    }
+
     println!("initializing logger...");
 
     let mut et = Evetrade::new();
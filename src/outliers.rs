@@ -0,0 +1,123 @@
+use crate::history::MarketHistory;
+use crate::settings::Settings;
+use crate::types::Order;
+
+// What happens to buy orders that look like bait
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutlierPolicy {
+    Ignore,
+    Flag,    // Kept, with the reason shown next to them
+    Exclude, // Dropped in preprocessing
+}
+
+// Why a buy order looks too good to be true
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutlierReason {
+    AboveHistory { average: f32 },
+    AboveMedianSell { median: f32 },
+    TinyVolume { median: f32 },
+    HugeMinVolume { daily_volume: f32 },
+}
+
+impl std::fmt::Display for OutlierReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OutlierReason::AboveHistory { average } => {
+                write!(f, "far above the regional average of {:.2} ISK", average)
+            }
+            OutlierReason::AboveMedianSell { median } => {
+                write!(f, "far above the median sell price of {:.2} ISK", median)
+            }
+            OutlierReason::TinyVolume { median } => write!(
+                f,
+                "tiny volume above the median sell price of {:.2} ISK",
+                median
+            ),
+            OutlierReason::HugeMinVolume { daily_volume } => write!(
+                f,
+                "minimum volume above the {:.0} units the region trades a day",
+                daily_volume
+            ),
+        }
+    }
+}
+
+// Buy orders are what we sell into, an inflated one makes any type look profitable.
+// Prices are compared to what the type actually traded at and to what it is offered for.
+pub struct OutlierDetector {
+    policy: OutlierPolicy,
+    price_ratio: f32,      // Times the reference price a buy order may be at most
+    tiny_volume: f32,      // Units below which an order only needs a smaller premium
+    tiny_price_ratio: f32, // Times the median sell price a tiny order may be at most
+}
+
+impl OutlierDetector {
+    pub fn new(settings: &Settings) -> Self {
+        OutlierDetector {
+            policy: settings.get_outlier_policy(),
+            price_ratio: settings.get_outlier_price_ratio(),
+            tiny_volume: settings.get_outlier_tiny_volume(),
+            tiny_price_ratio: settings.get_outlier_tiny_price_ratio(),
+        }
+    }
+
+    pub fn get_policy(&self) -> OutlierPolicy {
+        self.policy
+    }
+
+    // Sell orders have to be the type's full book, before any filtering
+    pub fn get_median_price(sell_orders: &[Order]) -> Option<f32> {
+        let mut prices: Vec<f32> = sell_orders.iter().map(|order| order.price).collect();
+        if prices.is_empty() {
+            return None;
+        }
+
+        prices.sort_by(|a, b| a.total_cmp(b));
+        let middle = prices.len() / 2;
+        if prices.len().is_multiple_of(2) {
+            Some((prices[middle - 1] + prices[middle]) / 2.0)
+        } else {
+            Some(prices[middle])
+        }
+    }
+
+    // The first reason that applies, None if the buy order looks fair
+    pub fn check(
+        &self,
+        buy_order: &Order,
+        median_sell: Option<f32>,
+        history: &MarketHistory,
+    ) -> Option<OutlierReason> {
+        if self.policy == OutlierPolicy::Ignore {
+            return None;
+        }
+
+        let type_id = buy_order.order_type.type_id;
+        if let Some(average) = history.get_average_price(buy_order.region_id, type_id) {
+            if buy_order.price > average * self.price_ratio {
+                return Some(OutlierReason::AboveHistory { average });
+            }
+        }
+
+        if let Some(median) = median_sell {
+            if buy_order.price > median * self.price_ratio {
+                return Some(OutlierReason::AboveMedianSell { median });
+            }
+            if buy_order.volume <= self.tiny_volume
+                && buy_order.price > median * self.tiny_price_ratio
+            {
+                return Some(OutlierReason::TinyVolume { median });
+            }
+        }
+
+        // Nobody sells that much at once, the order is only there to look filled
+        if let Some(daily_volume) = history.get_daily_volume(buy_order.region_id, type_id) {
+            if buy_order.min_volume > 1.0 && buy_order.min_volume > daily_volume {
+                return Some(OutlierReason::HugeMinVolume { daily_volume });
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,689 @@
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::jumps::JumpMatrix;
+use crate::types::{StargateKind, System};
+
+// Cheapest a single jump can be in any mode, keeps the heuristic admissible
+const MIN_JUMP_WEIGHT: f32 = 1.0;
+const METERS_PER_LIGHT_YEAR: f64 = 9_460_730_472_580_800.0;
+pub const HIGHSEC_SECURITY: f32 = 0.5;
+const FIRST_WORMHOLE_SYSTEM_ID: u32 = 31000000;
+const LAST_WORMHOLE_SYSTEM_ID: u32 = 31999999;
+
+// Mirrors the in-game autopilot route preferences.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathMode {
+    Shortest,
+    Safer,
+    LessSecure,
+}
+
+impl PathMode {
+    pub fn get_gatecamp_flag(&self) -> &'static str {
+        match self {
+            PathMode::Shortest => "shortest",
+            PathMode::Safer => "secure",
+            PathMode::LessSecure => "insecure",
+        }
+    }
+}
+
+// What a capital ship's jump route is optimised for
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JumpObjective {
+    MinimumJumps,
+    MinimumFatigue,
+}
+
+// Capital ships jump straight to a cyno within range instead of taking stargates.
+// Cynos can't be lit in highsec or wormhole space, so those are never jumped to.
+#[derive(Debug, Clone, Copy)]
+pub struct JumpDrive {
+    pub range: f64,             // Light years, after skills
+    pub fatigue_reduction: f64, // Percent of the distance that doesn't count for fatigue
+    pub objective: JumpObjective,
+}
+
+pub fn get_light_years(meters: f64) -> f64 {
+    meters / METERS_PER_LIGHT_YEAR
+}
+
+// Heap entry for Dijkstra and A*, BinaryHeap is a max-heap so the ordering is reversed.
+// Estimate is the cost so far plus the heuristic, or just the cost for Dijkstra.
+struct Node {
+    estimate: f32,
+    cost: f32,
+    jumps: usize,
+    system_id: u32,
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Node {}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Node {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimate
+            .total_cmp(&self.estimate)
+            .then_with(|| other.jumps.cmp(&self.jumps))
+    }
+}
+
+// Parts of the map a search has to route around, used to find alternative paths
+#[derive(Default)]
+struct Detour {
+    systems: HashSet<u32>,
+    stargates: HashSet<(u32, u32)>, // Origin and destination system
+}
+
+// Result of a single-source search, can be queried for any reached system.
+// A backwards search holds the paths from every reached system to the origin instead.
+pub struct PathTree<'a> {
+    systems: &'a HashMap<u32, System>,
+    origin: u32,
+    previous: HashMap<u32, (u32, usize)>, // Key: system id, value: predecessor and jumps from origin
+    is_backwards: bool,
+}
+
+impl<'a> PathTree<'a> {
+    pub fn get_jumps(&self, destination: u32) -> Option<usize> {
+        self.previous.get(&destination).map(|&(_, jumps)| jumps)
+    }
+
+    // Systems from origin to destination, both included, or the other way round for a backwards
+    // search so that the path can be flown as it is
+    pub fn get_path(&self, destination: u32) -> Option<Vec<System>> {
+        let mut path = Vec::new();
+        let mut current = destination;
+
+        loop {
+            let &(next, _) = self.previous.get(&current)?;
+            path.push(self.systems.get(&current)?.clone());
+            if current == self.origin {
+                break;
+            }
+            current = next;
+        }
+
+        if !self.is_backwards {
+            path.reverse();
+        }
+        Some(path)
+    }
+}
+
+pub struct Pathfinder<'a> {
+    systems: &'a HashMap<u32, System>,
+    mode: PathMode,
+    security_treshold: f32,
+    longest_jump_distance: f64, // Meters, no gate reaches further
+    jump_matrix: &'a JumpMatrix,
+    avoided_systems: HashSet<u32>,
+    custom_edges: Vec<(u32, u32)>, // Origin and destination system of every non-stargate edge
+    jump_drive: Option<JumpDrive>,
+    jump_graph: HashMap<u32, Vec<(u32, f32)>>, // Key: system id, value: cyno destination and weight
+    reverse_jump_graph: HashMap<u32, Vec<(u32, f32)>>, // Key: cyno destination, value: origin and weight
+}
+
+impl<'a> Pathfinder<'a> {
+    pub fn new(
+        systems: &'a HashMap<u32, System>,
+        mode: PathMode,
+        security_treshold: f32,
+        longest_jump_distance: f64,
+        jump_matrix: &'a JumpMatrix,
+    ) -> Self {
+        Pathfinder {
+            systems,
+            mode,
+            security_treshold,
+            longest_jump_distance,
+            jump_matrix,
+            avoided_systems: HashSet::new(),
+            custom_edges: systems
+                .values()
+                .flat_map(|system| &system.stargates)
+                .filter(|stargate| stargate.kind != StargateKind::Stargate)
+                .map(|stargate| (stargate.origin, stargate.destination))
+                .collect(),
+            jump_drive: None,
+            jump_graph: HashMap::new(),
+            reverse_jump_graph: HashMap::new(),
+        }
+    }
+
+    // Switches from stargates to jumping between every two systems within range
+    pub fn set_jump_drive(&mut self, jump_drive: JumpDrive) {
+        let is_cyno_allowed = |system: &System| {
+            !(FIRST_WORMHOLE_SYSTEM_ID..=LAST_WORMHOLE_SYSTEM_ID).contains(&system.id)
+                && system.get_display_security() < HIGHSEC_SECURITY
+        };
+        let destinations: Vec<&System> = self
+            .systems
+            .values()
+            .filter(|system| is_cyno_allowed(system))
+            .collect();
+
+        self.jump_graph = self
+            .systems
+            .par_iter()
+            .filter(|(system_id, _)| {
+                !(FIRST_WORMHOLE_SYSTEM_ID..=LAST_WORMHOLE_SYSTEM_ID).contains(*system_id)
+            })
+            .map(|(&system_id, system)| {
+                let mut jumps: Vec<(u32, f32)> = destinations
+                    .iter()
+                    .filter(|destination| destination.id != system_id)
+                    .filter_map(|destination| {
+                        let distance =
+                            get_light_years(system.position.distance(&destination.position));
+                        (distance <= jump_drive.range)
+                            .then(|| (destination.id, Self::get_jump_weight(jump_drive, distance)))
+                    })
+                    .collect();
+                jumps.sort_unstable_by_key(|(destination, _)| *destination);
+                (system_id, jumps)
+            })
+            .collect();
+
+        // Highsec can be jumped out of but not into, the way back is a different graph
+        let mut reverse_jump_graph: HashMap<u32, Vec<(u32, f32)>> = HashMap::new();
+        for (&system_id, jumps) in &self.jump_graph {
+            for &(destination, weight) in jumps {
+                reverse_jump_graph
+                    .entry(destination)
+                    .or_default()
+                    .push((system_id, weight));
+            }
+        }
+        for jumps in reverse_jump_graph.values_mut() {
+            jumps.sort_unstable_by_key(|(origin, _)| *origin);
+        }
+        self.reverse_jump_graph = reverse_jump_graph;
+        self.jump_drive = Some(jump_drive);
+    }
+
+    // Systems that are never entered, like the autopilot avoidance list
+    pub fn set_avoided_systems(&mut self, avoided_systems: HashSet<u32>) {
+        self.avoided_systems = avoided_systems;
+    }
+
+    // A* from origin to a single destination
+    pub fn compute_path(&self, origin: u32, destination: u32) -> Option<Vec<System>> {
+        self.search(origin, usize::MAX, None, Some(destination), None, false)
+            .get_path(destination)
+    }
+
+    // Up to count loopless paths from origin to destination, best first (Yen's algorithm).
+    // Every alternative branches off a better path, routing around the gates it took.
+    pub fn compute_alternative_paths(
+        &self,
+        origin: u32,
+        destination: u32,
+        count: usize,
+    ) -> Vec<Vec<System>> {
+        let mut paths: Vec<Vec<u32>> = Vec::new();
+        let mut candidates: Vec<(f32, Vec<u32>)> = Vec::new();
+
+        if let Some(path) = self.compute_path(origin, destination) {
+            paths.push(path.iter().map(|system| system.id).collect());
+        }
+
+        while !paths.is_empty() && paths.len() < count {
+            let last = &paths[paths.len() - 1];
+
+            for i in 0..last.len() - 1 {
+                let root = &last[..=i];
+                let mut detour = Detour {
+                    systems: root[..i].iter().copied().collect(),
+                    stargates: HashSet::new(),
+                };
+                for path in &paths {
+                    if path.len() > i + 1 && &path[..=i] == root {
+                        detour.stargates.insert((path[i], path[i + 1]));
+                    }
+                }
+
+                let spur = match self
+                    .search(
+                        root[i],
+                        usize::MAX,
+                        None,
+                        Some(destination),
+                        Some(&detour),
+                        false,
+                    )
+                    .get_path(destination)
+                {
+                    Some(spur) => spur,
+                    None => continue,
+                };
+
+                let mut path = root[..i].to_vec();
+                path.extend(spur.iter().map(|system| system.id));
+                if paths.contains(&path) || candidates.iter().any(|(_, other)| *other == path) {
+                    continue;
+                }
+                candidates.push((self.get_path_cost(&path), path));
+            }
+
+            // Cheapest candidate first, fewer jumps on a tie
+            let best = candidates
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| a.0.total_cmp(&b.0).then(a.1.len().cmp(&b.1.len())))
+                .map(|(i, _)| i);
+            match best {
+                Some(i) => paths.push(candidates.swap_remove(i).1),
+                None => break,
+            }
+        }
+
+        paths
+            .iter()
+            .map(|path| {
+                path.iter()
+                    .map(|system_id| self.systems[system_id].clone())
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Dijkstra from origin to every system reachable within max_jumps
+    pub fn compute_paths_from(&self, origin: u32, max_jumps: usize) -> PathTree<'a> {
+        self.search(origin, max_jumps, None, None, None, false)
+    }
+
+    // Dijkstra backwards from destination, paths lead from every system reached to it.
+    // Gates work both ways, cyno jumps don't.
+    pub fn compute_paths_to(&self, destination: u32, max_jumps: usize) -> PathTree<'a> {
+        self.search(destination, max_jumps, None, None, None, true)
+    }
+
+    // Like compute_paths_from, but skips systems that can't make it to target within max_jumps
+    // any more. Far fewer systems are expanded when the budget is tight.
+    pub fn compute_paths_towards(
+        &self,
+        origin: u32,
+        max_jumps: usize,
+        target: u32,
+    ) -> PathTree<'a> {
+        self.search(origin, max_jumps, Some(target), None, None, false)
+    }
+
+    // Lower bound on the jumps between two systems. A path either sticks to stargates or takes
+    // at least one custom edge, with stargates up to the first one and after the last one.
+    // A jump drive can't cover more than its range in a single jump.
+    pub fn get_min_jumps(&self, from: u32, to: u32) -> usize {
+        if let Some(jump_drive) = self.jump_drive {
+            return match (self.systems.get(&from), self.systems.get(&to)) {
+                (Some(from), Some(to)) => {
+                    let jumps =
+                        get_light_years(from.position.distance(&to.position)) / jump_drive.range;
+                    (jumps - 1e-6).ceil().max(0.0) as usize
+                }
+                _ => 0,
+            };
+        }
+
+        let direct = self.get_min_gate_jumps(from, to);
+        if self.custom_edges.is_empty() {
+            return direct;
+        }
+
+        let to_edge = self
+            .custom_edges
+            .iter()
+            .map(|&(origin, _)| self.get_min_gate_jumps(from, origin))
+            .min()
+            .unwrap_or(0);
+        let from_edge = self
+            .custom_edges
+            .iter()
+            .map(|&(_, destination)| self.get_min_gate_jumps(destination, to))
+            .min()
+            .unwrap_or(0);
+
+        direct.min(to_edge + 1 + from_edge)
+    }
+
+    // Routing around low security space only adds jumps to the plain gate count,
+    // and no gate spans more than the longest one
+    fn get_min_gate_jumps(&self, from: u32, to: u32) -> usize {
+        if let Some(jumps) = self.jump_matrix.get_jumps(from, to) {
+            return jumps;
+        }
+
+        if self.longest_jump_distance <= 0.0 {
+            return 0;
+        }
+
+        match (self.systems.get(&from), self.systems.get(&to)) {
+            (Some(from), Some(to)) => {
+                let jumps = from.position.distance(&to.position) / self.longest_jump_distance;
+                // Positions are rounded, don't let that cost a jump
+                (jumps - 1e-6).ceil().max(0.0) as usize
+            }
+            _ => 0,
+        }
+    }
+
+    fn search(
+        &self,
+        origin: u32,
+        max_jumps: usize,
+        target: Option<u32>,
+        destination: Option<u32>,
+        detour: Option<&Detour>,
+        is_backwards: bool,
+    ) -> PathTree<'a> {
+        let get_heuristic = |system_id: u32| match destination {
+            Some(destination) => {
+                self.get_min_jumps(system_id, destination) as f32 * self.get_min_jump_weight()
+            }
+            None => 0.0,
+        };
+
+        let mut costs: HashMap<u32, f32> = HashMap::from([(origin, 0.0)]);
+        let mut previous = HashMap::from([(origin, (origin, 0))]);
+        let mut queue = BinaryHeap::from([Node {
+            estimate: get_heuristic(origin),
+            cost: 0.0,
+            jumps: 0,
+            system_id: origin,
+        }]);
+
+        while let Some(Node {
+            cost,
+            jumps,
+            system_id,
+            ..
+        }) = queue.pop()
+        {
+            if cost > costs.get(&system_id).copied().unwrap_or(f32::INFINITY) {
+                continue;
+            }
+
+            if destination == Some(system_id) {
+                break;
+            }
+
+            if jumps >= max_jumps {
+                continue;
+            }
+
+            let system = match self.systems.get(&system_id) {
+                Some(system) => system,
+                None => continue,
+            };
+
+            let mut relax = |next_system_id: u32, weight: f32| {
+                if !self.is_system_allowed(next_system_id) {
+                    return;
+                }
+
+                if detour.is_some_and(|detour| {
+                    detour.systems.contains(&next_system_id)
+                        || detour.stargates.contains(&(system_id, next_system_id))
+                }) {
+                    return;
+                }
+
+                if let Some(target) = target {
+                    if jumps + 1 + self.get_min_jumps(next_system_id, target) > max_jumps {
+                        return;
+                    }
+                }
+
+                let next_cost = cost + weight;
+                let best_cost = costs.get(&next_system_id).copied().unwrap_or(f32::INFINITY);
+
+                if next_cost < best_cost {
+                    costs.insert(next_system_id, next_cost);
+                    previous.insert(next_system_id, (system_id, jumps + 1));
+                    queue.push(Node {
+                        estimate: next_cost + get_heuristic(next_system_id),
+                        cost: next_cost,
+                        jumps: jumps + 1,
+                        system_id: next_system_id,
+                    });
+                }
+            };
+
+            if self.jump_drive.is_some() {
+                let jump_graph = if is_backwards {
+                    &self.reverse_jump_graph
+                } else {
+                    &self.jump_graph
+                };
+                for &(next_system_id, weight) in jump_graph.get(&system_id).into_iter().flatten() {
+                    relax(next_system_id, weight);
+                }
+            } else {
+                for stargate in &system.stargates {
+                    relax(stargate.destination, self.get_weight(stargate.weight));
+                }
+            }
+        }
+
+        PathTree {
+            systems: self.systems,
+            origin,
+            previous,
+            is_backwards,
+        }
+    }
+
+    // Systems below the security threshold or on the avoidance list are never entered,
+    // paths route around them instead
+    pub fn is_system_allowed(&self, system_id: u32) -> bool {
+        !self.is_system_avoided(system_id)
+            && self
+                .systems
+                .get(&system_id)
+                .is_some_and(|system| system.get_display_security() >= self.security_treshold)
+    }
+
+    pub fn is_system_avoided(&self, system_id: u32) -> bool {
+        self.avoided_systems.contains(&system_id)
+    }
+
+    // Same cost the search would have found for this path
+    fn get_path_cost(&self, path: &[u32]) -> f32 {
+        path.windows(2)
+            .map(|pair| {
+                if self.jump_drive.is_some() {
+                    return self
+                        .jump_graph
+                        .get(&pair[0])
+                        .and_then(|jumps| jumps.iter().find(|(next, _)| *next == pair[1]))
+                        .map_or(f32::INFINITY, |&(_, weight)| weight);
+                }
+
+                self.systems[&pair[0]]
+                    .stargates
+                    .iter()
+                    .find(|stargate| stargate.destination == pair[1])
+                    .map_or(f32::INFINITY, |stargate| self.get_weight(stargate.weight))
+            })
+            .sum()
+    }
+
+    // Fatigue multiplies with every jump, so adding up its logarithm finds the least of it
+    fn get_jump_weight(jump_drive: JumpDrive, light_years: f64) -> f32 {
+        match jump_drive.objective {
+            JumpObjective::MinimumJumps => 1.0,
+            JumpObjective::MinimumFatigue => {
+                let effective_distance = light_years * (1.0 - jump_drive.fatigue_reduction / 100.0);
+                (1.0 + effective_distance).ln() as f32
+            }
+        }
+    }
+
+    fn get_min_jump_weight(&self) -> f32 {
+        match self.jump_drive {
+            Some(JumpDrive {
+                objective: JumpObjective::MinimumFatigue,
+                ..
+            }) => 0.0,
+            _ => MIN_JUMP_WEIGHT,
+        }
+    }
+
+    // Stargate weights range from 1 (highsec destination) to 10 (nullsec destination)
+    fn get_weight(&self, stargate_weight: f32) -> f32 {
+        match self.mode {
+            PathMode::Shortest => 1.0,
+            PathMode::Safer => stargate_weight,
+            PathMode::LessSecure => 11.0 - stargate_weight,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Stargate, Vector3};
+
+    fn system(id: u32, security_status: f32, x: f64, links: &[(u32, StargateKind)]) -> System {
+        System {
+            id,
+            name: format!("S{}", id),
+            security_status,
+            stargates: links
+                .iter()
+                .map(|&(destination, kind)| Stargate {
+                    origin: id,
+                    destination,
+                    weight: 1.0,
+                    kind,
+                })
+                .collect(),
+            position: Vector3 {
+                x: x * METERS_PER_LIGHT_YEAR,
+                y: 0.0,
+                z: 0.0,
+            },
+            constellation_id: 1,
+            region_id: 1,
+        }
+    }
+
+    // Gates both ways between every pair, systems a light year apart along their id
+    fn gate_map(gates: &[(u32, u32)], wormholes: &[(u32, u32)]) -> HashMap<u32, System> {
+        let mut links: HashMap<u32, Vec<(u32, StargateKind)>> = HashMap::new();
+        for (edges, kind) in [
+            (gates, StargateKind::Stargate),
+            (wormholes, StargateKind::Wormhole),
+        ] {
+            for &(a, b) in edges {
+                links.entry(a).or_default().push((b, kind));
+                links.entry(b).or_default().push((a, kind));
+            }
+        }
+
+        links
+            .into_iter()
+            .map(|(id, links)| (id, system(id, 1.0, id as f64, &links)))
+            .collect()
+    }
+
+    fn get_ids(path: &[System]) -> Vec<u32> {
+        path.iter().map(|system| system.id).collect()
+    }
+
+    #[test]
+    fn alternative_paths_come_cheapest_first() {
+        let systems = gate_map(
+            &[
+                (1, 2),
+                (2, 6),
+                (1, 3),
+                (3, 4),
+                (4, 6),
+                (1, 5),
+                (5, 7),
+                (7, 8),
+                (8, 6),
+            ],
+            &[],
+        );
+        let jump_matrix = JumpMatrix::new(&systems);
+        let pathfinder = Pathfinder::new(&systems, PathMode::Shortest, -1.0, 0.0, &jump_matrix);
+
+        let paths: Vec<Vec<u32>> = pathfinder
+            .compute_alternative_paths(1, 6, 3)
+            .iter()
+            .map(|path| get_ids(path))
+            .collect();
+
+        assert_eq!(
+            paths,
+            vec![vec![1, 2, 6], vec![1, 3, 4, 6], vec![1, 5, 7, 8, 6]]
+        );
+    }
+
+    // The wormhole skips most of the line, the heuristic mustn't make A* miss it
+    #[test]
+    fn a_star_takes_wormhole_shortcut_like_dijkstra() {
+        let systems = gate_map(&[(1, 2), (2, 3), (3, 4), (4, 5), (5, 6)], &[(1, 6)]);
+        let jump_matrix = JumpMatrix::new(&systems);
+        let pathfinder = Pathfinder::new(&systems, PathMode::Safer, -1.0, 1.0, &jump_matrix);
+
+        let a_star = get_ids(&pathfinder.compute_path(1, 5).unwrap());
+        let dijkstra = get_ids(
+            &pathfinder
+                .compute_paths_from(1, usize::MAX)
+                .get_path(5)
+                .unwrap(),
+        );
+
+        assert_eq!(a_star, vec![1, 6, 5]);
+        assert_eq!(
+            pathfinder.get_path_cost(&a_star),
+            pathfinder.get_path_cost(&dijkstra)
+        );
+    }
+
+    // Highsec can be jumped out of, but no cyno gets us back in
+    #[test]
+    fn backwards_search_follows_jumps_the_right_way() {
+        let systems: HashMap<u32, System> = [
+            system(1, 0.9, 0.0, &[]),
+            system(2, 0.3, 2.0, &[]),
+            system(3, 0.1, 4.0, &[]),
+        ]
+        .into_iter()
+        .map(|system| (system.id, system))
+        .collect();
+        let jump_matrix = JumpMatrix::new(&systems);
+        let mut pathfinder = Pathfinder::new(&systems, PathMode::Safer, -1.0, 0.0, &jump_matrix);
+        pathfinder.set_jump_drive(JumpDrive {
+            range: 3.0,
+            fatigue_reduction: 0.0,
+            objective: JumpObjective::MinimumJumps,
+        });
+
+        let to_lowsec = pathfinder.compute_paths_to(3, 5);
+        assert_eq!(get_ids(&to_lowsec.get_path(1).unwrap()), vec![1, 2, 3]);
+
+        let to_highsec = pathfinder.compute_paths_to(1, 5);
+        assert_eq!(to_highsec.get_jumps(2), None);
+        assert_eq!(to_highsec.get_jumps(3), None);
+    }
+}
@@ -1,314 +1,1327 @@
-use log::{debug, info};
-use std::collections::HashMap;
+use log::{debug, info, warn};
+use rayon::prelude::*;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::esi::ESI;
+use crate::fees::FeeModel;
+use crate::fuel::FuelModel;
+use crate::groups::{ItemFilter, ItemGroups};
+use crate::history::MarketHistory;
+use crate::jumps::JumpMatrix;
+use crate::outliers::{OutlierDetector, OutlierPolicy};
+use crate::pathfinder::{PathMode, PathTree, Pathfinder, HIGHSEC_SECURITY};
 use crate::route::Route;
-use crate::settings::SETTINGS;
-use crate::types::{Order, OrderGroup, System, Type};
+use crate::settings::{Settings, SETTINGS};
+use crate::ships::{self, ShipCapacity, ShipHolds, ASTEROID_CATEGORY_ID};
+use crate::sizer::{TradeBooks, TradeSizer};
+use crate::types::{
+    LocationFilter, Order, OrderGroup, OrderRange, System, TradeCandidate, TradePair, Type,
+    Waypoint,
+};
 
-#[derive(Debug)]
+const MAX_ROUTES: usize = 10;
+
+// Orders grouped by location, lists keep the price ordering from preprocessing
+struct OrderIndex<'o> {
+    sell: HashMap<u32, HashMap<u32, Vec<&'o Order>>>, // Key: system id, then type id
+    buy: HashMap<u32, HashMap<u32, Vec<&'o Order>>>,  // Key: type id, then system id
+}
+
+// Systems with at least one station market, the only places an order can be filled from
+struct MarketSystems {
+    systems: HashMap<u32, u32>,      // Key: system id, value: region id
+    regions: HashMap<u32, Vec<u32>>, // Key: region id
+}
+
+// Shared by every expansion of one route search
+struct SearchContext<'s> {
+    index: OrderIndex<'s>,
+    home_paths: Option<PathTree<'s>>, // Paths to the system routes have to finish in
+}
+
+#[derive(Debug, Default)]
 struct PreprocessStats {
     initial_types: usize,
+    removed_unknown_types: usize,
+    removed_item_groups: usize,
     removed_empty: usize,
+    removed_avoided_orders: usize,
+    removed_security_orders: usize,
+    removed_location_orders: usize,
+    removed_illiquid_orders: usize,
+    removed_outlier_orders: usize,
+    flagged_outlier_orders: usize,
     removed_volume: usize,
     removed_unprofitable: usize,
     final_types: usize,
 }
 
+impl PreprocessStats {
+    fn merge(self, other: PreprocessStats) -> PreprocessStats {
+        PreprocessStats {
+            initial_types: self.initial_types + other.initial_types,
+            removed_unknown_types: self.removed_unknown_types + other.removed_unknown_types,
+            removed_item_groups: self.removed_item_groups + other.removed_item_groups,
+            removed_empty: self.removed_empty + other.removed_empty,
+            removed_avoided_orders: self.removed_avoided_orders + other.removed_avoided_orders,
+            removed_security_orders: self.removed_security_orders + other.removed_security_orders,
+            removed_location_orders: self.removed_location_orders + other.removed_location_orders,
+            removed_illiquid_orders: self.removed_illiquid_orders + other.removed_illiquid_orders,
+            removed_outlier_orders: self.removed_outlier_orders + other.removed_outlier_orders,
+            flagged_outlier_orders: self.flagged_outlier_orders + other.flagged_outlier_orders,
+            removed_volume: self.removed_volume + other.removed_volume,
+            removed_unprofitable: self.removed_unprofitable + other.removed_unprofitable,
+            final_types: self.final_types + other.final_types,
+        }
+    }
+}
+
 pub struct OrderProcessor<'a> {
     orders: &'a mut HashMap<u32, OrderGroup>,
     systems: &'a HashMap<u32, System>,
     types: &'a HashMap<u32, Type>,
-    mean_jump_distance: f64,
-    cargo_volume: f32,
+    pathfinder: Pathfinder<'a>,
+    jump_matrix: &'a JumpMatrix,
+    history: &'a MarketHistory,
+    outliers: OutlierDetector,
+    item_groups: &'a ItemGroups,
+    included_items: Option<ItemFilter>, // None lets every type through, unlike an empty filter
+    excluded_items: ItemFilter,
+    sizer: TradeSizer,
+    fees: FeeModel,
+    fuel: Option<FuelModel<'a>>,
+    path_mode: PathMode,
+    capacity: ShipCapacity,
     initial_capital: f32,
     percentage_treshold: f32,
     max_jumps: u16,
+    seconds_per_jump: u32,
+    start_time: i64,
+    buy_locations: LocationFilter,
+    sell_locations: LocationFilter,
+    start_system: Option<u32>,
+    home_system: Option<u32>,
+    round_trip: bool,
+    direction_limit: Option<usize>,
+    alternative_paths: usize,
+    max_daily_volume_share: Option<f32>,
+    min_daily_volume: f32,
 }
 
 impl<'a> OrderProcessor<'a> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         orders: &'a mut HashMap<u32, OrderGroup>,
         systems: &'a HashMap<u32, System>,
         types: &'a HashMap<u32, Type>,
-        mean_jump_distance: f64,
+        longest_jump_distance: f64,
+        jump_matrix: &'a JumpMatrix,
+        history: &'a MarketHistory,
+        item_groups: &'a ItemGroups,
+        ship_holds: &HashMap<u32, ShipHolds>,
     ) -> Self {
         let settings = SETTINGS.lock().unwrap();
         let initial_capital = settings.get_initial_capital();
-        let cargo_volume = settings.get_ship_cargo_volume();
+        let capacity = ships::resolve_capacity(&settings, types, ship_holds);
         let percentage_treshold = settings.get_percentage_treshold();
         let max_jumps = settings.get_max_jumps();
+        let path_mode = settings.get_path_mode();
+        let security_treshold = settings.get_security_treshold();
+        let fees = FeeModel::new(&settings);
+        let seconds_per_jump = settings.get_seconds_per_jump();
+        let start_system = settings.get_start_system();
+
+        // A round trip finishes where it started, unless told to end somewhere else
+        let round_trip = settings.get_round_trip();
+        let mut home_system = settings.get_end_system();
+        if home_system.is_none() && round_trip {
+            if start_system.is_none() {
+                info!("No start system set, round trips will end where they started");
+            }
+            home_system = start_system;
+        }
+
+        let mut pathfinder = Pathfinder::new(
+            systems,
+            path_mode,
+            security_treshold,
+            longest_jump_distance,
+            jump_matrix,
+        );
+        pathfinder.set_avoided_systems(Self::resolve_avoided_systems(systems, &settings));
+        if let Some(jump_drive) = settings.get_jump_drive() {
+            pathfinder.set_jump_drive(jump_drive);
+
+            // Cynos can't be lit in highsec, so nothing jumps back into it
+            if let Some(home) = home_system.and_then(|home| systems.get(&home)) {
+                if home.get_display_security() >= HIGHSEC_SECURITY {
+                    warn!(
+                        "{} is highsec, routes can't jump back there to finish",
+                        home.name
+                    );
+                }
+            }
+        }
+        let fuel = FuelModel::new(&settings, orders, &fees, jump_matrix);
 
         OrderProcessor {
-            orders: orders,
-            systems: systems,
-            types: types,
-            mean_jump_distance: mean_jump_distance,
-            cargo_volume: cargo_volume,
-            initial_capital: initial_capital,
-            percentage_treshold: percentage_treshold,
-            max_jumps: max_jumps,
+            orders,
+            systems,
+            types,
+            pathfinder,
+            jump_matrix,
+            history,
+            outliers: OutlierDetector::new(&settings),
+            item_groups,
+            included_items: Self::resolve_included_items(item_groups, &settings),
+            excluded_items: item_groups.resolve_filter(settings.get_excluded_item_groups()),
+            sizer: TradeSizer::new(capacity, percentage_treshold, fees.clone()),
+            fees,
+            fuel,
+            path_mode,
+            capacity,
+            initial_capital,
+            percentage_treshold,
+            max_jumps,
+            seconds_per_jump,
+            start_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64,
+            buy_locations: settings.get_buy_locations().clone(),
+            sell_locations: settings.get_sell_locations().clone(),
+            start_system,
+            home_system,
+            round_trip,
+            direction_limit: settings.get_direction_limit(),
+            alternative_paths: settings.get_alternative_paths(),
+            max_daily_volume_share: settings.get_max_daily_volume_share(),
+            min_daily_volume: settings.get_min_daily_volume(),
+        }
+    }
+
+    // An include list stays in force even when none of its names are known
+    fn resolve_included_items(item_groups: &ItemGroups, settings: &Settings) -> Option<ItemFilter> {
+        let names = settings.get_included_item_groups();
+        if names.is_empty() {
+            return None;
+        }
+
+        let filter = item_groups.resolve_filter(names);
+        if filter.is_empty() {
+            warn!("None of the item groups to include are known, no type will be traded");
         }
+        Some(filter)
+    }
+
+    // Systems can be avoided by id or name, constellations and regions add all of their systems
+    fn resolve_avoided_systems(
+        systems: &HashMap<u32, System>,
+        settings: &Settings,
+    ) -> HashSet<u32> {
+        let mut avoided_systems = HashSet::new();
+
+        for entry in settings.get_avoided_systems() {
+            match System::find_id(systems, entry) {
+                Some(system_id) => {
+                    avoided_systems.insert(system_id);
+                }
+                None => warn!("Unknown system to avoid: {}", entry),
+            }
+        }
+
+        let avoided_constellations = settings.get_avoided_constellations();
+        let avoided_regions = settings.get_avoided_regions();
+        for system in systems.values() {
+            if avoided_constellations.contains(&system.constellation_id)
+                || avoided_regions.contains(&system.region_id)
+            {
+                avoided_systems.insert(system.id);
+            }
+        }
+
+        if !avoided_systems.is_empty() {
+            info!("Avoiding {} systems", avoided_systems.len());
+        }
+
+        avoided_systems
     }
 
     pub fn compute(&mut self) -> Vec<Route> {
-        let mut routes: Vec<Route> = Vec::new();
+        let markets = self.collect_market_systems();
+        self.run_preprocessing();
+
+        info!("Searching for routes...");
+        let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let routes = self.search_routes(&markets);
+        let end = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        info!("Searching routes took {:?}ms", (end - start).as_millis());
+        debug!("Found {} routes", routes.len());
+
+        routes
+    }
+
+    pub fn compute_hauling(&mut self) -> Vec<TradePair> {
+        self.run_preprocessing();
+
+        info!("Searching for station hauls...");
+        let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let pairs = self.search_hauling();
+        let end = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        info!("Searching hauls took {:?}ms", (end - start).as_millis());
+        debug!("Found {} hauls", pairs.len());
 
+        pairs
+    }
+
+    fn run_preprocessing(&mut self) {
         info!("Preprocessing orders...");
         let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
         let stats = self.preprocess_orders();
         let end = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
         info!("Processing orders took {:?}ms", (end - start).as_millis());
         debug!("Preprocessing results: {:?}", stats);
+        info!(
+            "Kept {} of {} types after preprocessing",
+            stats.final_types, stats.initial_types
+        );
+    }
 
-        // for order in &self.orders {
-        //     let mut route = Route::new();
-        //     route.add_order(order.clone());
-        //     routes.push(route);
-        // }
+    // Has to run before preprocessing, which drops the orders of most stations
+    fn collect_market_systems(&self) -> MarketSystems {
+        let mut markets = MarketSystems {
+            systems: HashMap::new(),
+            regions: HashMap::new(),
+        };
 
-        routes
+        for order_group in self.orders.values() {
+            for order in order_group.buy.iter().chain(&order_group.sell) {
+                if markets
+                    .systems
+                    .insert(order.system_id, order.region_id)
+                    .is_none()
+                {
+                    markets
+                        .regions
+                        .entry(order.region_id)
+                        .or_default()
+                        .push(order.system_id);
+                }
+            }
+        }
+
+        markets
     }
 
+    // Types are independent of each other, so they are handled in parallel and their stats summed
     fn preprocess_orders(&mut self) -> PreprocessStats {
-        let mut stats = PreprocessStats {
-            initial_types: self.orders.len(),
-            removed_empty: 0,
-            removed_volume: 0,
-            removed_unprofitable: 0,
-            final_types: 0,
+        let mut orders = std::mem::take(self.orders);
+        let mut stats = orders
+            .par_iter_mut()
+            .map(|(type_id, order_group)| self.preprocess_type(*type_id, order_group))
+            .reduce(PreprocessStats::default, PreprocessStats::merge);
+
+        // Removed types are left empty
+        stats.initial_types = orders.len();
+        orders.retain(|_, group| !group.buy.is_empty() && !group.sell.is_empty());
+        stats.final_types = orders.len();
+        *self.orders = orders;
+
+        stats
+    }
+
+    fn preprocess_type(&self, type_id: u32, order_group: &mut OrderGroup) -> PreprocessStats {
+        let mut stats = PreprocessStats::default();
+
+        // Orders can name types the universe data doesn't know yet
+        let item_type = match self.types.get(&type_id) {
+            Some(item_type) => item_type,
+            None => {
+                Self::clear_group(order_group);
+                stats.removed_unknown_types += 1;
+                return stats;
+            }
         };
 
+        // Blueprints, SKINs and the like are left out by what they are, not by their orders
+        if !self.is_type_allowed(item_type) {
+            Self::clear_group(order_group);
+            stats.removed_item_groups += 1;
+            return stats;
+        }
+
+        // Taken from the whole book, before filtering leaves only a few orders to go by
+        let median_sell = OutlierDetector::get_median_price(&order_group.sell);
+
         // First pass: Remove obviously invalid orders and sort them
-        let type_ids: Vec<_> = self.orders.keys().cloned().collect();
-        for type_id in type_ids {
-            if let Some(order_group) = self.orders.get_mut(&type_id) {
-                if order_group.buy.is_empty() || order_group.sell.is_empty() {
-                    self.orders.remove(&type_id);
-                    stats.removed_empty += 1;
-                    continue;
+        let order_count = order_group.buy.len() + order_group.sell.len();
+        order_group
+            .buy
+            .retain(|order| !self.pathfinder.is_system_avoided(order.system_id));
+        order_group
+            .sell
+            .retain(|order| !self.pathfinder.is_system_avoided(order.system_id));
+        stats.removed_avoided_orders +=
+            order_count - order_group.buy.len() - order_group.sell.len();
+
+        let order_count = order_group.buy.len() + order_group.sell.len();
+        order_group
+            .buy
+            .retain(|order| self.pathfinder.is_system_allowed(order.system_id));
+        order_group
+            .sell
+            .retain(|order| self.pathfinder.is_system_allowed(order.system_id));
+        stats.removed_security_orders +=
+            order_count - order_group.buy.len() - order_group.sell.len();
+
+        // Ranged buy orders may be sold into from somewhere else, they are filtered when indexed
+        let order_count = order_group.sell.len();
+        order_group
+            .sell
+            .retain(|order| self.buy_locations.allows_order(order));
+        stats.removed_location_orders += order_count - order_group.sell.len();
+
+        // A big margin is worth nothing if the market barely trades the type
+        let order_count = order_group.buy.len();
+        order_group
+            .buy
+            .retain(|order| self.is_liquid(order.region_id, type_id));
+        stats.removed_illiquid_orders += order_count - order_group.buy.len();
+
+        // Inflated buy orders are how margin trading scams make a type look profitable
+        for order in order_group.buy.iter_mut() {
+            order.outlier = self.outliers.check(order, median_sell, self.history);
+        }
+        let order_count = order_group.buy.len();
+        if self.outliers.get_policy() == OutlierPolicy::Exclude {
+            order_group.buy.retain(|order| order.outlier.is_none());
+        }
+        stats.removed_outlier_orders += order_count - order_group.buy.len();
+        stats.flagged_outlier_orders += order_group
+            .buy
+            .iter()
+            .filter(|order| order.outlier.is_some())
+            .count();
+
+        if order_group.buy.is_empty() || order_group.sell.is_empty() {
+            Self::clear_group(order_group);
+            stats.removed_empty += 1;
+            return stats;
+        }
+
+        let room = if self.fits_ore_hold(item_type.group_id) {
+            self.capacity.get_total()
+        } else {
+            self.capacity.cargo
+        };
+        if item_type.volume > room {
+            Self::clear_group(order_group);
+            stats.removed_volume += 1;
+            return stats;
+        }
+
+        // Descending for buy, ascending for sell. By the price after fees, since structures
+        // charge broker fees of their own and a cheaper order may cost more there.
+        order_group.buy.sort_by(|a, b| {
+            self.fees
+                .get_net_sell_price(b)
+                .total_cmp(&self.fees.get_net_sell_price(a))
+        });
+        order_group.sell.sort_by(|a, b| {
+            self.fees
+                .get_net_buy_price(a)
+                .total_cmp(&self.fees.get_net_buy_price(b))
+        });
+
+        let percentage = self
+            .fees
+            .get_profit_percentage(&order_group.sell[0], &order_group.buy[0]);
+        if percentage < self.percentage_treshold {
+            Self::clear_group(order_group);
+            stats.removed_unprofitable += 1;
+            return stats;
+        }
+
+        let mut truncate_buy_at = order_group.buy.len();
+        let mut truncate_sell_at = order_group.sell.len();
+
+        // For each buy order, find if it has ANY profitable pairs
+        // P.S. Should I refactor this? Looks too complex, but we should normally break early
+        for (i, buy_order) in order_group.buy.iter().enumerate() {
+            let mut has_profitable_trade = false;
+            for sell_order in order_group.sell.iter() {
+                let percentage = self.fees.get_profit_percentage(sell_order, buy_order);
+                if percentage >= self.percentage_treshold {
+                    has_profitable_trade = true;
+                    break;
                 }
+            }
+            if has_profitable_trade {
+                truncate_buy_at = i + 1; // Keep this buy order
+            } else {
+                break; // No need to check further buy orders (they'll be worse)
+            }
+        }
+
+        // Doing the same thingy for sell orders
+        for (k, sell_order) in order_group.sell.iter().enumerate() {
+            let mut has_profitable_trade = false;
+            for buy_order in order_group.buy.iter() {
+                let percentage = self.fees.get_profit_percentage(sell_order, buy_order);
+                if percentage >= self.percentage_treshold {
+                    has_profitable_trade = true;
+                    break;
+                }
+            }
+            if has_profitable_trade {
+                truncate_sell_at = k + 1;
+            } else {
+                break;
+            }
+        }
+
+        order_group.buy.truncate(truncate_buy_at);
+        order_group.sell.truncate(truncate_sell_at);
 
-                if let Some(item_type) = self.types.get(&type_id) {
-                    if item_type.volume > self.cargo_volume {
-                        self.orders.remove(&type_id);
-                        stats.removed_volume += 1;
+        if order_group.buy.is_empty() || order_group.sell.is_empty() {
+            Self::clear_group(order_group);
+            stats.removed_unprofitable += 1;
+        }
+
+        stats
+    }
+
+    fn clear_group(order_group: &mut OrderGroup) {
+        order_group.buy.clear();
+        order_group.sell.clear();
+    }
+
+    fn search_routes(&self, markets: &MarketSystems) -> Vec<Route> {
+        let mut routes = Vec::new();
+        let mut candidates = BinaryHeap::new();
+        let context = SearchContext {
+            index: self.index_orders(markets),
+            home_paths: self.home_system.map(|home| {
+                self.pathfinder
+                    .compute_paths_to(home, self.max_jumps as usize)
+            }),
+        };
+        let start_paths = self.start_system.map(|start| {
+            self.pathfinder
+                .compute_paths_from(start, self.max_jumps as usize)
+        });
+
+        // Seed the queue with the first leg from every system we can buy something in,
+        // otherwise the first popped root would starve all the others.
+        // Getting there from the start system already costs jumps.
+        let mut roots: Vec<u32> = context.index.sell.keys().copied().collect();
+        roots.sort_unstable();
+        let seeds: Vec<TradeCandidate> = roots
+            .par_iter()
+            .flat_map_iter(|&system_id| {
+                let mut root = TradeCandidate {
+                    profit_per_jump: 0.0,
+                    profit: 0.0,
+                    jumps: 0,
+                    system_id,
+                    capital: self.initial_capital,
+                    waypoints: Vec::new(),
+                    visited: HashSet::from([system_id]),
+                    taken: HashMap::new(),
+                    origin: self.start_system.unwrap_or(system_id),
+                    is_closed: false,
+                };
+
+                if let Some(start_paths) = &start_paths {
+                    let path = match start_paths.get_path(system_id) {
+                        Some(path) => path,
+                        None => return Vec::new(),
+                    };
+                    root.jumps = path.len() - 1;
+                    let fuel_cost = self.get_fuel_cost(root.origin, &path);
+                    root.profit -= fuel_cost;
+                    root.capital -= fuel_cost;
+                    if root.jumps > 0 {
+                        root.waypoints
+                            .extend(path.into_iter().map(Waypoint::System));
+                    }
+                }
+
+                self.expand_candidate(&root, &context)
+            })
+            .collect();
+        candidates.extend(seeds);
+
+        while let Some(candidate) = candidates.pop() {
+            if routes.len() >= MAX_ROUTES {
+                break;
+            }
+
+            // Round trips only become routes once they made it home, which goes back into the
+            // queue since the way back changes the profit per jump
+            if candidate.is_closed {
+                routes.push(self.build_route(&candidate));
+                continue;
+            } else if self.is_round_trip() {
+                if let Some(closed) = self.close_candidate(&candidate, &context) {
+                    candidates.push(closed);
+                }
+            } else if candidate.profit > 0.0 {
+                // Getting to the first market may have cost more fuel than the legs made so far
+                routes.push(self.build_route(&candidate));
+            }
+
+            candidates.extend(self.expand_candidate(&candidate, &context));
+        }
+
+        routes
+    }
+
+    fn index_orders(&self, markets: &MarketSystems) -> OrderIndex<'_> {
+        let mut index = OrderIndex {
+            sell: HashMap::new(),
+            buy: HashMap::new(),
+        };
+        let mut jump_ranges: HashMap<(u32, u8), Vec<u32>> = HashMap::new();
+
+        // Order ranges count plain jumps, whatever our own route preferences are.
+        // Pushing in the preprocessed order keeps every list sorted by price after fees.
+        for (&type_id, order_group) in self.orders.iter() {
+            for sell_order in &order_group.sell {
+                index
+                    .sell
+                    .entry(sell_order.system_id)
+                    .or_default()
+                    .entry(type_id)
+                    .or_default()
+                    .push(sell_order);
+            }
+
+            // A buy order can be sold into from every system within its range
+            for buy_order in &order_group.buy {
+                let systems = match buy_order.range {
+                    OrderRange::Station | OrderRange::SolarSystem => {
+                        std::slice::from_ref(&buy_order.system_id)
+                    }
+                    OrderRange::Jumps(jumps) => jump_ranges
+                        .entry((buy_order.system_id, jumps))
+                        .or_insert_with(|| {
+                            markets
+                                .systems
+                                .keys()
+                                .copied()
+                                .filter(|&system_id| {
+                                    self.jump_matrix
+                                        .get_jumps(buy_order.system_id, system_id)
+                                        .is_some_and(|distance| distance <= jumps as usize)
+                                })
+                                .collect()
+                        }),
+                    OrderRange::Region => markets
+                        .regions
+                        .get(&buy_order.region_id)
+                        .map_or(&[][..], |systems| systems),
+                };
+
+                // Only systems we may sell in count, the order's own station may be allowed by
+                // itself even when its system isn't
+                for &system_id in systems {
+                    let is_allowed = self
+                        .sell_locations
+                        .allows_system(system_id, markets.systems[&system_id])
+                        || (system_id == buy_order.system_id
+                            && self.sell_locations.allows_order(buy_order));
+                    if !is_allowed {
                         continue;
                     }
-                } else {
-                    self.orders.remove(&type_id);
+
+                    index
+                        .buy
+                        .entry(type_id)
+                        .or_default()
+                        .entry(system_id)
+                        .or_default()
+                        .push(buy_order);
+                }
+            }
+        }
+
+        index
+    }
+
+    // Legs are packed in parallel, they only share read-only order books
+    fn expand_candidate(
+        &self,
+        candidate: &TradeCandidate,
+        context: &SearchContext,
+    ) -> Vec<TradeCandidate> {
+        let paths = self.compute_leg_paths(candidate, self.home_system);
+
+        // Every later leg still has to make it home, so this bound never gets looser
+        let legs = self.collect_legs(
+            candidate,
+            &context.index,
+            &paths,
+            self.home_system,
+            |destination| !candidate.visited.contains(&destination),
+            |destination| Self::get_return_jumps(context, destination),
+        );
+
+        legs.into_par_iter()
+            .filter_map(|(destination, (return_jumps, books))| {
+                let next = self.extend_candidate(candidate, destination, &paths, &books)?;
+                Some(TradeCandidate {
+                    profit_per_jump: next.profit / (next.jumps + return_jumps).max(1) as f32,
+                    ..next
+                })
+            })
+            .collect()
+    }
+
+    // Takes the best cargo on the way home, or flies back empty if nothing beats that.
+    // The origin is fair game as well, whatever earlier legs sold into is left out of the books.
+    fn close_candidate(
+        &self,
+        candidate: &TradeCandidate,
+        context: &SearchContext,
+    ) -> Option<TradeCandidate> {
+        let origin_paths;
+        let home_paths = match &context.home_paths {
+            Some(home_paths) => home_paths,
+            None => {
+                origin_paths = self
+                    .pathfinder
+                    .compute_paths_to(candidate.origin, self.max_jumps as usize);
+                &origin_paths
+            }
+        };
+
+        let home = self.home_system.unwrap_or(candidate.origin);
+        let paths = self.compute_leg_paths(candidate, Some(home));
+        let legs = self.collect_legs(
+            candidate,
+            &context.index,
+            &paths,
+            Some(home),
+            |destination| {
+                destination == candidate.origin || !candidate.visited.contains(&destination)
+            },
+            |destination| home_paths.get_jumps(destination),
+        );
+
+        let get_profit_per_jump = |option: &TradeCandidate| -> Option<f32> {
+            let return_jumps = home_paths.get_jumps(option.system_id)?;
+            if option.jumps + return_jumps > self.max_jumps as usize {
+                return None;
+            }
+            Some(option.profit / (option.jumps + return_jumps).max(1) as f32)
+        };
+
+        let mut best = get_profit_per_jump(candidate).map(|score| (score, candidate.clone()));
+        for (destination, (_, books)) in legs {
+            let option = match self.extend_candidate(candidate, destination, &paths, &books) {
+                Some(option) => option,
+                None => continue,
+            };
+            let score = match get_profit_per_jump(&option) {
+                Some(score) => score,
+                None => continue,
+            };
+            if best
+                .as_ref()
+                .is_none_or(|(best_score, _)| score > *best_score)
+            {
+                best = Some((score, option));
+            }
+        }
+
+        let (profit_per_jump, mut closed) = best?;
+        let path = home_paths.get_path(closed.system_id)?;
+        closed.jumps += path.len() - 1;
+        let fuel_cost = self.get_fuel_cost(closed.origin, &path);
+        closed.profit -= fuel_cost;
+        closed.capital -= fuel_cost;
+        // Flying home may cost more fuel than the whole route made
+        if closed.profit <= 0.0 {
+            return None;
+        }
+        if path.len() > 1 {
+            closed
+                .waypoints
+                .extend(path.into_iter().map(Waypoint::System));
+        }
+
+        Some(TradeCandidate {
+            profit_per_jump: profit_per_jump - fuel_cost / closed.jumps.max(1) as f32,
+            is_closed: true,
+            ..closed
+        })
+    }
+
+    // Paths out of the candidate's system within the jumps it has left.
+    // Systems too far away to still make it home in time are never expanded.
+    fn compute_leg_paths(&self, candidate: &TradeCandidate, home: Option<u32>) -> PathTree<'a> {
+        let remaining_jumps = (self.max_jumps as usize).saturating_sub(candidate.jumps);
+        match home {
+            Some(home) => {
+                self.pathfinder
+                    .compute_paths_towards(candidate.system_id, remaining_jumps, home)
+            }
+            None => self
+                .pathfinder
+                .compute_paths_from(candidate.system_id, remaining_jumps),
+        }
+    }
+
+    // Books of every type that can be bought where the candidate is and sold at an allowed
+    // destination, grouped by destination along with the jumps left from there to home.
+    // Sorted by destination, so that ties between legs always break the same way.
+    fn collect_legs<'s>(
+        &'s self,
+        candidate: &'s TradeCandidate,
+        index: &'s OrderIndex,
+        paths: &PathTree,
+        home: Option<u32>,
+        is_destination_allowed: impl Fn(u32) -> bool,
+        get_return_jumps: impl Fn(u32) -> Option<usize>,
+    ) -> Vec<(u32, (usize, Vec<TradeBooks<'s>>))> {
+        let mut legs: HashMap<u32, (usize, Vec<TradeBooks>)> = HashMap::new();
+        let local_sell_orders = match index.sell.get(&candidate.system_id) {
+            Some(local_sell_orders) => local_sell_orders,
+            None => return Vec::new(),
+        };
+
+        // Every type that can be bought here and sold there shares one cargo load.
+        // Types go in by id, the sizer breaks ties between equally good ones in book order.
+        let purchase_time = self.get_arrival_time(candidate.jumps);
+        let mut type_ids: Vec<&u32> = local_sell_orders.keys().collect();
+        type_ids.sort_unstable();
+        for type_id in type_ids {
+            let sell_orders = &local_sell_orders[type_id];
+            // Types whose buy orders are all outside the sell locations were never indexed
+            let destinations = match index.buy.get(type_id) {
+                Some(destinations) => destinations,
+                None => continue,
+            };
+            for (&destination, buy_orders) in destinations {
+                if !is_destination_allowed(destination) {
                     continue;
                 }
 
-                // Descending for buy, ascending for sell
-                order_group
-                    .buy
-                    .sort_by(|a, b| b.price.partial_cmp(&a.price).unwrap());
-                order_group
-                    .sell
-                    .sort_by(|a, b| a.price.partial_cmp(&b.price).unwrap());
+                // Cheap top of book check before walking the order books
+                if self
+                    .fees
+                    .get_profit_percentage(sell_orders[0], buy_orders[0])
+                    < self.percentage_treshold
+                {
+                    continue;
+                }
+
+                let jumps = match paths.get_jumps(destination) {
+                    Some(jumps) => candidate.jumps + jumps,
+                    None => continue,
+                };
+                let return_jumps = match get_return_jumps(destination) {
+                    Some(return_jumps) => return_jumps,
+                    None => continue,
+                };
+                if jumps + return_jumps > self.max_jumps as usize {
+                    continue;
+                }
+
+                legs.entry(destination)
+                    .or_insert_with(|| (return_jumps, Vec::new()))
+                    .1
+                    .push(TradeBooks {
+                        item_type: &self.types[type_id],
+                        sell_orders,
+                        buy_orders,
+                        purchase_time,
+                        sale_time: self.get_arrival_time(jumps),
+                        max_units: self.get_max_units(destination, *type_id),
+                        fits_ore_hold: self.fits_ore_hold(self.types[type_id].group_id),
+                        taken: &candidate.taken,
+                    });
+            }
+        }
+
+        let mut legs: Vec<_> = legs.into_iter().collect();
+        legs.sort_unstable_by_key(|(destination, _)| *destination);
+
+        // Only the destinations heading the most towards home are worth packing
+        if let (Some(limit), Some(home)) = (self.direction_limit, home) {
+            let similarities: HashMap<u32, f64> = legs
+                .iter()
+                .map(|(destination, _)| {
+                    let similarity =
+                        self.get_direction_similarity(candidate.system_id, *destination, home);
+                    (*destination, similarity)
+                })
+                .collect();
+            legs.sort_by(|(a, _), (b, _)| similarities[b].total_cmp(&similarities[a]));
+            legs.truncate(limit);
+        }
+
+        legs
+    }
+
+    // How well heading from one system to another points towards the target, from 0 to 1
+    fn get_direction_similarity(&self, from: u32, to: u32, target: u32) -> f64 {
+        match (
+            self.systems.get(&from),
+            self.systems.get(&to),
+            self.systems.get(&target),
+        ) {
+            (Some(from), Some(to), Some(target)) => {
+                let heading = from.position.direction_to(&to.position);
+                let wanted = from.position.direction_to(&target.position);
+                heading.similarity(&wanted, 1.0, 0.0)
             }
+            _ => 0.0,
+        }
+    }
+
+    // The candidate after hauling the best load to the destination, ranked by its own profit
+    fn extend_candidate(
+        &self,
+        candidate: &TradeCandidate,
+        destination: u32,
+        paths: &PathTree,
+        books: &[TradeBooks],
+    ) -> Option<TradeCandidate> {
+        let trade = self.sizer.pack(books, candidate.capital)?;
+        let path = paths.get_path(destination)?;
+        let jumps = candidate.jumps + path.len() - 1;
+        // A load that doesn't pay for the fuel isn't worth the jumps
+        let fuel_cost = self.get_fuel_cost(candidate.origin, &path);
+        if fuel_cost > 0.0 && trade.get_profit() <= fuel_cost {
+            return None;
+        }
+        let profit = candidate.profit + trade.get_profit() - fuel_cost;
 
-            if let Some(order_group) = self.orders.get_mut(&type_id) {
-                let buy_price = order_group.buy[0].price;
-                let sell_price = order_group.sell[0].price;
-                if (buy_price - sell_price) / sell_price < self.percentage_treshold {
-                    self.orders.remove(&type_id);
-                    stats.removed_unprofitable += 1;
+        // A ranged buy order is listed under every system in range, and a sell order may be
+        // bought from again when the route comes back, so later legs only get what is left
+        let mut taken = candidate.taken.clone();
+        for order in trade.sell_orders.iter().chain(&trade.buy_orders) {
+            *taken.entry(order.order_id).or_default() += order.volume;
+        }
+
+        let mut waypoints = candidate.waypoints.clone();
+        waypoints.extend(trade.sell_orders.into_iter().map(Waypoint::Order));
+        waypoints.extend(path.into_iter().map(Waypoint::System));
+        waypoints.extend(trade.buy_orders.into_iter().map(Waypoint::Order));
+
+        let mut visited = candidate.visited.clone();
+        visited.insert(destination);
+
+        Some(TradeCandidate {
+            profit_per_jump: profit / jumps.max(1) as f32,
+            profit,
+            jumps,
+            system_id: destination,
+            capital: candidate.capital - trade.cost + trade.revenue - fuel_cost,
+            waypoints,
+            visited,
+            taken,
+            origin: candidate.origin,
+            is_closed: false,
+        })
+    }
+
+    // Every profitable single hop between two stations, sized against the initial capital.
+    // Buy orders are only sold into at their own station, ranges don't apply here.
+    // Jumps include getting to the first station from the start system and going home after.
+    fn search_hauling(&self) -> Vec<TradePair> {
+        let mut sell: HashMap<u32, HashMap<u32, HashMap<u32, Vec<&Order>>>> = HashMap::new(); // Key: system, type, station
+        let mut buy: HashMap<u32, HashMap<u32, Vec<&Order>>> = HashMap::new(); // Key: type, station
+
+        for (&type_id, order_group) in self.orders.iter() {
+            for sell_order in &order_group.sell {
+                sell.entry(sell_order.system_id)
+                    .or_default()
+                    .entry(type_id)
+                    .or_default()
+                    .entry(sell_order.station_id)
+                    .or_default()
+                    .push(sell_order);
+            }
+
+            for buy_order in &order_group.buy {
+                if !self.sell_locations.allows_order(buy_order) {
                     continue;
                 }
 
-                let mut truncate_buy_at = order_group.buy.len();
-                let mut truncate_sell_at = order_group.sell.len();
-
-                // For each buy order, find if it has ANY profitable pairs
-                // P.S. Should I refactor this? Looks too complex, but we should normally break early
-                for (i, buy_order) in order_group.buy.iter().enumerate() {
-                    let mut has_profitable_trade = false;
-                    for sell_order in order_group.sell.iter() {
-                        let profit_ratio = (buy_order.price - sell_order.price) / sell_order.price;
-                        if profit_ratio >= self.percentage_treshold {
-                            has_profitable_trade = true;
-                            break;
-                        }
-                    }
-                    if has_profitable_trade {
-                        truncate_buy_at = i + 1; // Keep this buy order
-                    } else {
-                        break; // No need to check further buy orders (they'll be worse)
+                buy.entry(type_id)
+                    .or_default()
+                    .entry(buy_order.station_id)
+                    .or_default()
+                    .push(buy_order);
+            }
+        }
+
+        let start_paths = self.start_system.map(|start| {
+            self.pathfinder
+                .compute_paths_from(start, self.max_jumps as usize)
+        });
+        let home_paths = self.home_system.map(|home| {
+            self.pathfinder
+                .compute_paths_to(home, self.max_jumps as usize)
+        });
+        let nothing_taken = HashMap::new(); // Every pair is a trip of its own
+
+        sell.par_iter()
+            .flat_map_iter(|(&origin, local_types)| {
+                let mut pairs = Vec::new();
+                let approach_jumps = match &start_paths {
+                    Some(start_paths) => match start_paths.get_jumps(origin) {
+                        Some(jumps) => jumps,
+                        None => return pairs,
+                    },
+                    None => 0,
+                };
+                let remaining_jumps = (self.max_jumps as usize).saturating_sub(approach_jumps);
+                let paths = match self.home_system {
+                    Some(home) => {
+                        self.pathfinder
+                            .compute_paths_towards(origin, remaining_jumps, home)
                     }
-                }
+                    None => self.pathfinder.compute_paths_from(origin, remaining_jumps),
+                };
 
-                // Doing the same thingy for sell orders
-                for (k, sell_order) in order_group.sell.iter().enumerate() {
-                    let mut has_profitable_trade = false;
-                    for buy_order in order_group.buy.iter() {
-                        let profit_ratio = (buy_order.price - sell_order.price) / sell_order.price;
-                        if profit_ratio >= self.percentage_treshold {
-                            has_profitable_trade = true;
-                            break;
+                let mut type_ids: Vec<&u32> = local_types.keys().collect();
+                type_ids.sort_unstable();
+                for type_id in type_ids {
+                    let stations = &local_types[type_id];
+                    let item_type = &self.types[type_id];
+                    let buy_stations = match buy.get(type_id) {
+                        Some(buy_stations) => buy_stations,
+                        None => continue,
+                    };
+
+                    for (&buy_station_id, sell_orders) in stations {
+                        for (&sell_station_id, buy_orders) in buy_stations {
+                            if buy_station_id == sell_station_id {
+                                continue;
+                            }
+
+                            if self
+                                .fees
+                                .get_profit_percentage(sell_orders[0], buy_orders[0])
+                                < self.percentage_treshold
+                            {
+                                continue;
+                            }
+
+                            let destination = buy_orders[0].system_id;
+                            let jumps = match paths.get_jumps(destination) {
+                                Some(jumps) => approach_jumps + jumps,
+                                None => continue,
+                            };
+                            let return_jumps = match &home_paths {
+                                Some(home_paths) => match home_paths.get_jumps(destination) {
+                                    Some(return_jumps) => return_jumps,
+                                    None => continue,
+                                },
+                                None => 0,
+                            };
+                            if jumps + return_jumps > self.max_jumps as usize {
+                                continue;
+                            }
+
+                            let books = TradeBooks {
+                                item_type,
+                                sell_orders,
+                                buy_orders,
+                                purchase_time: self.get_arrival_time(approach_jumps),
+                                sale_time: self.get_arrival_time(jumps),
+                                max_units: self.get_max_units(destination, *type_id),
+                                fits_ore_hold: self.fits_ore_hold(item_type.group_id),
+                                taken: &nothing_taken,
+                            };
+                            let trade = match self.sizer.pack(&[books], self.initial_capital) {
+                                Some(trade) => trade,
+                                None => continue,
+                            };
+
+                            let units: f32 =
+                                trade.sell_orders.iter().map(|order| order.volume).sum();
+                            let buy_value: f32 = trade
+                                .sell_orders
+                                .iter()
+                                .map(|order| order.price * order.volume)
+                                .sum();
+                            let sell_value: f32 = trade
+                                .buy_orders
+                                .iter()
+                                .map(|order| order.price * order.volume)
+                                .sum();
+                            let fuel_cost = match paths.get_path(destination) {
+                                Some(path) => self.get_fuel_cost(origin, &path),
+                                None => 0.0,
+                            };
+                            let profit = trade.get_profit() - fuel_cost;
+                            let days_to_sell = self
+                                .history
+                                .get_daily_volume(buy_orders[0].region_id, *type_id)
+                                .map(|daily_volume| units / daily_volume);
+
+                            pairs.push(TradePair {
+                                buy_station_id,
+                                buy_system_id: origin,
+                                sell_station_id,
+                                sell_system_id: destination,
+                                order_type: item_type.clone(),
+                                potential_profit: profit,
+                                volume: units as i32,
+                                buy_price: buy_value / units,
+                                sell_price: sell_value / units,
+                                jumps: (jumps + return_jumps) as i32,
+                                profit_per_jump: profit / (jumps + return_jumps).max(1) as f32,
+                                roi: profit / trade.cost * 100.0,
+                                days_to_sell,
+                            });
                         }
                     }
-                    if has_profitable_trade {
-                        truncate_sell_at = k + 1;
-                    } else {
-                        break;
-                    }
                 }
 
-                order_group.buy.truncate(truncate_buy_at);
-                order_group.sell.truncate(truncate_sell_at);
+                pairs
+            })
+            .collect()
+    }
+
+    // Routes have to end at a fixed home system or back at their origin
+    fn is_round_trip(&self) -> bool {
+        self.home_system.is_some() || self.round_trip
+    }
+
+    // Jumps left to the home system once we are here, None if it can't be reached
+    fn get_return_jumps(context: &SearchContext, system_id: u32) -> Option<usize> {
+        match &context.home_paths {
+            Some(home_paths) => home_paths.get_jumps(system_id),
+            None => Some(0),
+        }
+    }
+
+    fn is_type_allowed(&self, item_type: &Type) -> bool {
+        self.included_items
+            .as_ref()
+            .is_none_or(|included_items| self.item_groups.matches(included_items, item_type))
+            && !self.item_groups.matches(&self.excluded_items, item_type)
+    }
+
+    fn fits_ore_hold(&self, group_id: u32) -> bool {
+        self.item_groups
+            .groups
+            .get(&group_id)
+            .is_some_and(|group| group.category_id == ASTEROID_CATEGORY_ID)
+    }
+
+    // Types without any history are only illiquid once there is history to go by
+    fn is_liquid(&self, region_id: u32, type_id: u32) -> bool {
+        self.min_daily_volume <= 0.0
+            || self
+                .history
+                .get_daily_volume(region_id, type_id)
+                .is_none_or(|daily_volume| daily_volume >= self.min_daily_volume)
+    }
+
+    // Selling more than a share of what the destination's region trades in a day floods it
+    fn get_max_units(&self, destination: u32, type_id: u32) -> f32 {
+        let share = match self.max_daily_volume_share {
+            Some(share) => share,
+            None => return f32::INFINITY,
+        };
+
+        self.systems
+            .get(&destination)
+            .and_then(|system| self.history.get_daily_volume(system.region_id, type_id))
+            .map_or(f32::INFINITY, |daily_volume| {
+                (daily_volume * share / 100.0).floor()
+            })
+    }
+
+    // Nothing unless we jump, the fuel is bought around where the route starts
+    fn get_fuel_cost(&self, origin: u32, path: &[System]) -> f32 {
+        self.fuel
+            .as_ref()
+            .map_or(0.0, |fuel| fuel.get_path_cost(origin, path))
+    }
+
+    // When we get somewhere after flying this many jumps from the start
+    fn get_arrival_time(&self, jumps: usize) -> i64 {
+        self.start_time + jumps as i64 * self.seconds_per_jump as i64
+    }
+
+    fn build_route(&self, candidate: &TradeCandidate) -> Route {
+        let mut route = Route::new();
+        route.set_path_mode(self.path_mode);
+        route.set_fee_model(self.fees.clone());
+        route.set_initial_capital(self.initial_capital);
+        let mut systems = Vec::new();
 
-                if order_group.buy.is_empty() || order_group.sell.is_empty() {
-                    self.orders.remove(&type_id);
-                    stats.removed_unprofitable += 1;
+        for waypoint in &candidate.waypoints {
+            match waypoint {
+                Waypoint::System(system) => systems.push(system.clone()),
+                Waypoint::Order(order) => {
+                    if !systems.is_empty() {
+                        self.add_path(&mut route, candidate.origin, std::mem::take(&mut systems));
+                    }
+                    if order.is_buy_order {
+                        let type_id = order.order_type.type_id;
+                        if let Some(daily_volume) =
+                            self.history.get_daily_volume(order.region_id, type_id)
+                        {
+                            route.add_daily_volume(order.region_id, type_id, daily_volume);
+                        }
+                    }
+                    route.add_order(order.clone());
                 }
             }
         }
 
-        self.orders
-            .retain(|_, group| !group.buy.is_empty() && !group.sell.is_empty());
-        stats.final_types = self.orders.len();
+        if !systems.is_empty() {
+            self.add_path(&mut route, candidate.origin, systems);
+        }
 
-        stats
+        route
     }
 
-    // Helper method to calculate maximum possible units for a trade
-    fn calculate_max_units(&self, type_id: u32, buy_order: &Order, sell_order: &Order) -> i32 {
-        let type_volume = self
-            .types
-            .get(&type_id)
-            .map(|t| t.volume)
-            .unwrap_or(f32::MAX);
-        let volume_limit = (self.cargo_volume / type_volume);
-        let capital_limit = (self.initial_capital / sell_order.price);
+    // Alternatives are only searched for the few routes that make it into the results
+    fn add_path(&self, route: &mut Route, origin: u32, path: Vec<System>) {
+        if let (Some(fuel), Some(first), Some(last)) = (&self.fuel, path.first(), path.last()) {
+            route.add_fuel(
+                (first.id, last.id),
+                fuel.get_fuel_type(),
+                fuel.get_path_fuel(&path),
+                fuel.get_path_cost(origin, &path),
+            );
+        }
 
-        volume_limit
-            .min(capital_limit)
-            .min(buy_order.volume)
-            .min(sell_order.volume)
-            .floor() as i32
+        if self.alternative_paths > 0 && path.len() > 1 {
+            let origin = path[0].id;
+            let destination = path[path.len() - 1].id;
+            let alternatives = self
+                .pathfinder
+                .compute_alternative_paths(origin, destination, self.alternative_paths + 1)
+                .into_iter()
+                .filter(|alternative| {
+                    alternative
+                        .iter()
+                        .map(|system| system.id)
+                        .ne(path.iter().map(|system| system.id))
+                })
+                .take(self.alternative_paths)
+                .collect();
+            route.add_alternatives(origin, destination, alternatives);
+        }
+
+        route.add_systems(path);
     }
 }
 
-/*
-def process_orders(self) -> list[Route]:
-    """
-    Find profitable trade routes considering:
-    - Ship cargo volume
-    - Initial capital (which can increase during trading)
-    - Maximum number of jumps
-    - Security threshold
-    - Profit percentage threshold
-
-    Returns a list of Route objects representing profitable trading paths
-    """
-    routes = []
-    visited_systems = set()
-
-    # Priority queue for route candidates
-    # Priority is -profit_per_jump to use min-heap as max-heap
-    candidates = []
-
-    # Initialize with potential starting points
-    # Look for sell orders that we can afford
-    for type_id in self.orders:
-        type_volume = self.types[type_id].volume
-        if type_volume > self.max_cargo:
-            continue
-
-        for system_id in self.graph:
-            sell_orders = self.graph[system_id][type_id]['sell_orders']
-            if not sell_orders:
-                continue
-
-            best_sell = sell_orders[0]  # Already sorted by price ascending
-            if best_sell.price * (self.max_cargo // type_volume) <= self.initial_capital:
-                heappush(candidates, (0, system_id, self.initial_capital, [], set()))
-
-    while candidates and len(routes) < 10:  # Limit to top 10 routes
-        _, current_system, current_capital, path, visited = heappop(candidates)
-
-        if len(path) // 2 >= self.max_jumps:  # Check if we've exceeded max jumps
-            continue
-
-        # Try to find profitable trades from current system
-        for type_id in self.orders:
-            type_volume = self.types[type_id].volume
-            max_units = min(self.max_cargo // type_volume,
-                          current_capital // min(o.price for o in self.graph[current_system][type_id]['sell_orders'] if o))
-
-            if max_units == 0:
-                continue
-
-            # Find best sell order in current system
-            sell_orders = [o for o in self.graph[current_system][type_id]['sell_orders']
-                         if o.volume >= max_units and o.price * max_units <= current_capital]
-
-            if not sell_orders:
-                continue
-
-            sell_order = sell_orders[0]  # Lowest price
-
-            # Look for profitable buy orders in other systems
-            for dest_system in self.graph:
-                if dest_system == current_system or dest_system in visited:
-                    continue
-
-                if self.systems[dest_system].security_status < Settings.security_treshold:
-                    continue
-
-                buy_orders = [o for o in self.graph[dest_system][type_id]['buy_orders']
-                            if o.volume >= max_units]
-
-                if not buy_orders:
-                    continue
-
-                buy_order = buy_orders[0]  # Highest price (sorted descending)
-
-                # Calculate potential profit
-                purchase_cost = sell_order.price * max_units
-                sell_revenue = buy_order.price * max_units
-                potential_profit = sell_revenue - purchase_cost
-
-                # Check profit percentage threshold
-                if Utils.get_profit_percentage(sell_order.price, buy_order.price) < Settings.percentage_treshold:
-                    continue
-
-                # Get path between systems
-                system_path = self.pathfinder.compute_path(current_system, dest_system)
-                if not system_path:
-                    continue
-
-                jumps = len(system_path) - 1
-                if len(path) // 2 + jumps > self.max_jumps:
-                    continue
-
-                profit_per_jump = potential_profit / jumps if jumps > 0 else 0
-
-                # Create new route candidate
-                new_path = list(path)
-                new_path.extend([sell_order, *system_path])
-                new_visited = set(visited)
-                new_visited.add(dest_system)
-
-                heappush(candidates,
-                        (-profit_per_jump,
-                         dest_system,
-                         current_capital - purchase_cost + sell_revenue,
-                         new_path,
-                         new_visited))
-
-        # If we have a valid path, create a route
-        if path:
-            route = Route()
-            for item in path:
-                if isinstance(item, Order):
-                    route.add_order(item)
-                else:
-                    route.add_systems(item)
-            routes.append(route)
-
-    return routes
-*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Stargate, StargateKind, Vector3};
+
+    fn system(id: u32, neighbours: &[u32]) -> System {
+        System {
+            id,
+            name: format!("S{}", id),
+            security_status: 1.0,
+            stargates: neighbours
+                .iter()
+                .map(|&destination| Stargate {
+                    origin: id,
+                    destination,
+                    weight: 1.0,
+                    kind: StargateKind::Stargate,
+                })
+                .collect(),
+            position: Vector3 {
+                x: id as f64 * 1e16,
+                y: 0.0,
+                z: 0.0,
+            },
+            constellation_id: 1,
+            region_id: 1,
+        }
+    }
+
+    fn order(order_id: u64, item_type: &Type, system_id: u32, price: f32, is_buy: bool) -> Order {
+        Order {
+            order_id,
+            is_buy_order: is_buy,
+            order_type: item_type.clone(),
+            price,
+            station_id: system_id * 10,
+            system_id,
+            region_id: 1,
+            volume: 100.0,
+            min_volume: 1.0,
+            range: if is_buy {
+                OrderRange::Region
+            } else {
+                OrderRange::Station
+            },
+            issued: 0,
+            duration: 90,
+            outlier: None,
+        }
+    }
+
+    // A region wide buy order is listed under every system, two legs must not fill it twice
+    #[test]
+    fn ranged_buy_order_is_filled_once_per_route() {
+        let systems: HashMap<u32, System> = [system(1, &[2]), system(2, &[1, 3]), system(3, &[2])]
+            .into_iter()
+            .map(|system| (system.id, system))
+            .collect();
+        let item_type = Type {
+            type_id: 34,
+            group_id: 18,
+            market_group_id: None,
+            name: "Tritanium".to_string(),
+            volume: 1.0,
+        };
+        let types = HashMap::from([(item_type.type_id, item_type.clone())]);
+        let mut orders = HashMap::from([(
+            item_type.type_id,
+            OrderGroup {
+                buy: vec![order(1, &item_type, 3, 20.0, true)],
+                sell: vec![
+                    order(2, &item_type, 1, 10.0, false),
+                    order(3, &item_type, 2, 10.0, false),
+                ],
+            },
+        )]);
+        let jump_matrix = JumpMatrix::new(&systems);
+        let history = MarketHistory::default();
+        let item_groups = ItemGroups::default();
+
+        let routes = OrderProcessor::new(
+            &mut orders,
+            &systems,
+            &types,
+            1e16,
+            &jump_matrix,
+            &history,
+            &item_groups,
+            &HashMap::new(),
+        )
+        .compute();
+
+        assert!(!routes.is_empty());
+        for route in &routes {
+            let mut filled: HashMap<u64, f32> = HashMap::new();
+            for waypoint in route.get_path() {
+                if let Waypoint::Order(order) = waypoint {
+                    if order.is_buy_order {
+                        *filled.entry(order.order_id).or_default() += order.volume;
+                    }
+                }
+            }
+            assert!(filled.values().all(|&units| units <= 100.0));
+        }
+    }
+}
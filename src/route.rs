@@ -1,7 +1,11 @@
 use log::error;
+use std::collections::HashMap;
 use std::fmt::Write;
 
-use crate::types::{Order, System, Waypoint};
+use crate::fees::FeeModel;
+use crate::fuel::FuelType;
+use crate::pathfinder::{get_light_years, PathMode};
+use crate::types::{Order, OrderRange, StargateKind, System, Waypoint};
 
 #[derive(Clone)]
 pub struct Route {
@@ -11,6 +15,15 @@ pub struct Route {
     representation: String,
     jumps: usize,
     profit_per_jump: f32,
+    path_mode: PathMode,
+    fees: FeeModel,
+    fees_total: f32,
+    initial_capital: Option<f32>,
+    alternatives: HashMap<(u32, u32), Vec<Vec<System>>>, // Key: first and last system of a leg
+    fuel: Option<(FuelType, f32)>, // Jump fuel burnt over the whole route, in units
+    fuel_cost: f32,
+    path_fuel_costs: HashMap<(u32, u32), f32>, // Key: first and last system of a path
+    daily_volumes: HashMap<(u32, u32), f32>,   // Key: region id and type id of what we sell
 }
 
 impl Route {
@@ -22,40 +35,110 @@ impl Route {
             representation: String::new(),
             jumps: 0,
             profit_per_jump: 0.0,
+            path_mode: PathMode::Safer,
+            fees: FeeModel::default(),
+            fees_total: 0.0,
+            initial_capital: None,
+            alternatives: HashMap::new(),
+            fuel: None,
+            fuel_cost: 0.0,
+            path_fuel_costs: HashMap::new(),
+            daily_volumes: HashMap::new(),
         }
     }
 
     pub fn add_systems(&mut self, systems: Vec<System>) {
+        // The path includes the system we start from, which is not a jump
+        self.jumps += systems.len().saturating_sub(1);
         for system in systems {
             self.path.push(Waypoint::System(system));
-            self.jumps += 1;
         }
         self.is_dirty = true;
     }
 
+    pub fn set_path_mode(&mut self, mode: PathMode) {
+        self.path_mode = mode;
+        self.is_dirty = true;
+    }
+
+    pub fn set_fee_model(&mut self, fees: FeeModel) {
+        self.fees = fees;
+        self.is_dirty = true;
+    }
+
+    // Enables reporting the wallet after every leg
+    pub fn set_initial_capital(&mut self, capital: f32) {
+        self.initial_capital = Some(capital);
+        self.is_dirty = true;
+    }
+
+    // Other ways of flying the leg between the first and last system of the path
+    pub fn add_alternatives(&mut self, origin: u32, destination: u32, paths: Vec<Vec<System>>) {
+        self.alternatives.insert((origin, destination), paths);
+        self.is_dirty = true;
+    }
+
+    // Jump drive fuel burnt along a path, paid for out of the profit
+    pub fn add_fuel(&mut self, path_ends: (u32, u32), fuel_type: FuelType, units: f32, cost: f32) {
+        let total = self.fuel.map_or(0.0, |(_, units)| units);
+        self.fuel = Some((fuel_type, total + units));
+        self.fuel_cost += cost;
+        *self.path_fuel_costs.entry(path_ends).or_default() += cost;
+        self.is_dirty = true;
+    }
+
+    // What the market sells on an average day, for telling how long our sales take
+    pub fn add_daily_volume(&mut self, region_id: u32, type_id: u32, daily_volume: f32) {
+        self.daily_volumes
+            .insert((region_id, type_id), daily_volume);
+        self.is_dirty = true;
+    }
+
     pub fn add_order(&mut self, order: Order) {
         self.path.push(Waypoint::Order(order));
         self.is_dirty = true;
     }
 
+    #[allow(dead_code)]
     pub fn get_jumps(&self) -> usize {
         self.jumps
     }
 
+    #[allow(dead_code)]
     pub fn get_path(&self) -> &Vec<Waypoint> {
         &self.path
     }
 
+    pub fn get_lowest_security_system(&self) -> Option<&System> {
+        Self::find_lowest_security(self.path.iter().filter_map(|point| match point {
+            Waypoint::System(system) => Some(system),
+            Waypoint::Order(_) => None,
+        }))
+    }
+
+    // By the security shown in game, a 0.45 system is highsec there and so it is here
+    fn find_lowest_security<'s>(systems: impl Iterator<Item = &'s System>) -> Option<&'s System> {
+        systems.min_by(|a, b| {
+            a.get_display_security()
+                .total_cmp(&b.get_display_security())
+                .then(a.security_status.total_cmp(&b.security_status))
+        })
+    }
+
     pub fn calculate_profit(&mut self) {
         let mut buy_total: f32 = 0.0;
         let mut sell_total: f32 = 0.0;
+        let mut gross_profit: f32 = 0.0;
 
         for point in &self.path {
+            // Filling a buy order is our sale, taking a sell order is our purchase
             if let Waypoint::Order(order) = point {
                 if order.is_buy_order {
-                    buy_total += order.price * order.volume;
+                    sell_total += self.fees.get_net_sell_price(order) * order.volume;
+                    gross_profit += order.price * order.volume;
                 } else {
-                    sell_total += order.price * order.volume;
+                    buy_total += self.fees.get_net_buy_price(order) * order.volume;
+                    gross_profit -= order.price * order.volume;
                 }
             }
         }
@@ -66,7 +149,8 @@ impl Route {
             error!("Missing buy or sell order.");
         }
 
-        self.profit = sell_total - buy_total;
+        self.fees_total = gross_profit - (sell_total - buy_total);
+        self.profit = sell_total - buy_total - self.fuel_cost;
         self.profit_per_jump = if self.jumps > 0 {
             (self.profit / self.jumps as f32).round()
         } else {
@@ -88,7 +172,7 @@ impl Route {
         self.profit_per_jump
     }
 
-    pub fn sort_routes(routes: &mut Vec<Route>) {
+    pub fn sort_routes(routes: &mut [Route]) {
         for route in routes.iter_mut() {
             route.get_profit_per_jump();
         }
@@ -99,6 +183,108 @@ impl Route {
         });
     }
 
+    // Consecutive orders of the same type and side are fills of a single trade
+    fn write_fills(&self, representation: &mut String, fills: &mut Vec<&Order>) {
+        let first = match fills.first() {
+            Some(first) => first,
+            None => return,
+        };
+
+        if fills.len() > 1 {
+            let units: f32 = fills.iter().map(|order| order.volume).sum();
+            let value: f32 = fills.iter().map(|order| order.volume * order.price).sum();
+            writeln!(
+                representation,
+                "\tBlended price: {:.2} ISK for {} units over {} orders.\n",
+                value / units,
+                units,
+                fills.len()
+            )
+            .unwrap();
+        }
+
+        let daily_volume = self
+            .daily_volumes
+            .get(&(first.region_id, first.order_type.type_id));
+        match (first.is_buy_order, daily_volume) {
+            (true, Some(&daily_volume)) if daily_volume > 0.0 => {
+                let units: f32 = fills.iter().map(|order| order.volume).sum();
+                writeln!(
+                    representation,
+                    "\tDays to sell: {:.1} at {:.0} units a day.",
+                    units / daily_volume,
+                    daily_volume
+                )
+                .unwrap();
+            }
+            (true, Some(_)) => {
+                writeln!(representation, "\tDays to sell: unknown, none sold lately.").unwrap();
+            }
+            _ => {}
+        }
+
+        writeln!(
+            representation,
+            "\n\tEve Market Browser: {}\n\n",
+            crate::urls::get_market_browser_url(first.order_type.type_id)
+        )
+        .unwrap();
+
+        fills.clear();
+    }
+
+    fn write_alternatives(&self, representation: &mut String, path_ends: Option<(u32, u32)>) {
+        let paths = match path_ends.and_then(|path_ends| self.alternatives.get(&path_ends)) {
+            Some(paths) => paths,
+            None => return,
+        };
+
+        for (i, path) in paths.iter().enumerate() {
+            let lowest = match Self::find_lowest_security(path.iter()) {
+                Some(lowest) => lowest,
+                None => continue,
+            };
+
+            writeln!(
+                representation,
+                "\tAlternative {}: {} jumps, lowest security: {} ({:.1})",
+                i + 1,
+                path.len() - 1,
+                lowest.name,
+                lowest.get_display_security()
+            )
+            .unwrap();
+            writeln!(
+                representation,
+                "\tEve Gatecamp Check: {}\n",
+                crate::urls::get_gatecamp_url(
+                    path.iter().map(|system| system.name.clone()).collect(),
+                    self.path_mode.get_gatecamp_flag()
+                )
+            )
+            .unwrap();
+        }
+    }
+
+    // Fuel is bought before flying a path, so it comes out of the capital right away
+    fn get_path_fuel_cost(&self, path_ends: Option<(u32, u32)>) -> f32 {
+        path_ends
+            .and_then(|path_ends| self.path_fuel_costs.get(&path_ends))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
+    fn write_capital(representation: &mut String, capital: Option<f32>, leg: usize) {
+        if let Some(capital) = capital {
+            writeln!(
+                representation,
+                "\tCapital after leg {}: {:.2} ISK\n",
+                leg, capital
+            )
+            .unwrap();
+        }
+    }
+
     pub fn represent(&mut self) -> String {
         if !self.is_dirty {
             return self.representation.clone();
@@ -110,50 +296,139 @@ impl Route {
 
         let mut representation = String::new();
         let mut systems = Vec::new();
-        let mut jumps = 1;
+        let mut fills = Vec::new();
+        let mut jumps = 0;
+        let mut capital = self.initial_capital;
+        let mut leg = 0;
+        let mut is_selling = false;
+        let mut path_ends: Option<(u32, u32)> = None; // First and last system of the current path
+        let mut previous_system: Option<&System> = None;
+
+        if let Some(initial_capital) = self.initial_capital {
+            writeln!(
+                representation,
+                "\tStarting capital: {:.2} ISK\n",
+                initial_capital
+            )
+            .unwrap();
+        }
 
         for point in &self.path {
             match point {
                 Waypoint::System(system) => {
+                    self.write_fills(&mut representation, &mut fills);
+
+                    // Wormholes, jump bridges and cyno jumps don't show up on the in-game map
+                    let connection = match previous_system {
+                        Some(previous) => match previous.get_connection_kind(system.id) {
+                            Some(StargateKind::Stargate) => String::new(),
+                            Some(kind) => format!(" [via {}]", kind),
+                            None => format!(
+                                " [jump drive, {:.2} ly]",
+                                get_light_years(previous.position.distance(&system.position))
+                            ),
+                        },
+                        None => String::new(),
+                    };
                     writeln!(
                         representation,
-                        "\t{}. {} ({:.2}) ->",
-                        jumps, system.name, system.security_status
+                        "\t{}. {} ({:.1}){} ->",
+                        jumps,
+                        system.name,
+                        system.get_display_security(),
+                        connection
                     )
                     .unwrap();
+                    previous_system = Some(system);
                     systems.push(system.name.clone());
                     jumps += 1;
+                    path_ends = Some((path_ends.map_or(system.id, |(first, _)| first), system.id));
                 }
                 Waypoint::Order(order) => {
+                    let ends = path_ends.take();
+                    self.write_alternatives(&mut representation, ends);
+                    capital = capital.map(|capital| capital - self.get_path_fuel_cost(ends));
+                    previous_system = None;
+
+                    let is_same_trade = fills.last().is_none_or(|last: &&Order| {
+                        last.is_buy_order == order.is_buy_order
+                            && last.order_type.type_id == order.order_type.type_id
+                    });
+                    if !is_same_trade {
+                        self.write_fills(&mut representation, &mut fills);
+                    }
+
+                    // A purchase after sales starts the next leg
+                    if is_selling && !order.is_buy_order {
+                        leg += 1;
+                        Self::write_capital(&mut representation, capital, leg);
+                    }
+                    is_selling = order.is_buy_order;
+
+                    capital = capital.map(|capital| {
+                        if order.is_buy_order {
+                            capital + self.fees.get_net_sell_price(order) * order.volume
+                        } else {
+                            capital - self.fees.get_net_buy_price(order) * order.volume
+                        }
+                    });
+
                     jumps = 0;
                     let order_type = if order.is_buy_order { "Buy" } else { "Sell" };
-                    writeln!(
+                    write!(
                         representation,
-                        "\n\t{} order for {} of {} ({:.2} ISK).\n",
+                        "\n\t{} order for {} of {} ({:.2} ISK",
                         order_type,
                         order.volume,
                         order.order_type.name,
                         order.volume * order.price
                     )
                     .unwrap();
-                    writeln!(
-                        representation,
-                        "\n\tEve Market Browser: {}\n\n",
-                        crate::urls::get_market_browser_url(order.order_type.type_id)
-                    )
-                    .unwrap();
+                    if order.is_buy_order && order.range != OrderRange::Station {
+                        write!(representation, ", range: {}", order.range).unwrap();
+                    }
+                    writeln!(representation, ").\n").unwrap();
+                    if let Some(outlier) = order.outlier {
+                        writeln!(representation, "\tSuspicious order: {}.\n", outlier).unwrap();
+                    }
+                    fills.push(order);
                 }
             }
         }
+        self.write_fills(&mut representation, &mut fills);
+        let ends = path_ends.take();
+        self.write_alternatives(&mut representation, ends);
+        capital = capital.map(|capital| capital - self.get_path_fuel_cost(ends));
+        if is_selling {
+            Self::write_capital(&mut representation, capital, leg + 1);
+        }
 
         writeln!(
             representation,
             "\n\nEve Gatecamp Check: {}\n",
-            crate::urls::get_gatecamp_url(systems, "secure")
+            crate::urls::get_gatecamp_url(systems, self.path_mode.get_gatecamp_flag())
         )
         .unwrap();
+        if let Some(system) = self.get_lowest_security_system() {
+            writeln!(
+                representation,
+                "Lowest security: {} ({:.1})\n",
+                system.name,
+                system.get_display_security()
+            )
+            .unwrap();
+        }
         writeln!(representation, "Total jumps: {}\n", self.jumps).unwrap();
         writeln!(representation, "Total profit: {:.2}\n", self.get_profit()).unwrap();
+        writeln!(representation, "Total fees: {:.2}\n", self.fees_total).unwrap();
+        if let Some((fuel_type, units)) = self.fuel {
+            writeln!(
+                representation,
+                "Total fuel: {} units of {} ({:.2} ISK)\n",
+                units, fuel_type, self.fuel_cost
+            )
+            .unwrap();
+        }
         writeln!(
             representation,
             "Profit per jump: {:.2}\n",
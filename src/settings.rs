@@ -1,13 +1,59 @@
+use std::collections::HashMap;
 use std::sync::Mutex;
 
+use crate::evetrade::OutputMode;
+use crate::fuel::FuelProfile;
+use crate::hauling::HaulingSort;
+use crate::outliers::OutlierPolicy;
+use crate::pathfinder::{JumpDrive, PathMode};
+use crate::ships::ShipProfile;
+use crate::types::LocationFilter;
+
 pub struct Settings {
     log_level: log::Level,
     update_universe_data: bool,
     percentage_treshold: f32,
-    ship_cargo_volume: f32,
+    ship_cargo_volume: f32, // Used when no ship profile is active
+    ship_profiles: Vec<ShipProfile>,
+    active_ship_profile: Option<String>, // Profile name, can be picked per run with --ship
     max_jumps: u16,
     initial_capital: f32,
     security_treshold: f32,
+    path_mode: PathMode,
+    accounting_level: u8,
+    broker_relations_level: u8,
+    faction_standing: f32,
+    corporation_standing: f32,
+    structure_broker_fees: HashMap<u32, f32>,
+    buy_with_orders: bool,
+    sell_with_orders: bool,
+    seconds_per_jump: u32,
+    output_mode: OutputMode,
+    hauling_sort: HaulingSort,
+    hauling_limit: usize,
+    min_profit: f32,
+    buy_locations: LocationFilter,  // Where we buy, i.e. sell orders
+    sell_locations: LocationFilter, // Where we sell, i.e. buy orders
+    start_system: Option<u32>,      // Where we are now, anywhere if not set
+    end_system: Option<u32>,        // Where routes have to finish, anywhere if not set
+    round_trip: bool,               // Finish where we started
+    direction_limit: Option<usize>, // Only this many destinations heading towards home per leg
+    alternative_paths: usize,       // Plan B paths shown for every leg
+    avoided_systems: Vec<String>,   // System ids or names
+    avoided_constellations: Vec<u32>,
+    avoided_regions: Vec<u32>,
+    custom_edges_path: String, // YAML or CSV with wormholes and jump bridges, optional
+    jump_drive: Option<JumpDrive>, // Cyno jumps instead of stargates, e.g. for jump freighters
+    fuel_profile: Option<FuelProfile>, // Prices the fuel of jump drive routes
+    history_days: u32,         // Days of market history to fetch, 0 to skip it
+    max_daily_volume_share: Option<f32>, // Most units of a type to sell, in percent of a day's volume
+    min_daily_volume: f32,               // Units a type has to sell on an average day
+    outlier_policy: OutlierPolicy,       // What to do with buy orders that look like scams
+    outlier_price_ratio: f32, // Times the history average or median sell a buy order may be at
+    outlier_tiny_volume: f32, // Units an order has to have at most to count as tiny
+    outlier_tiny_price_ratio: f32, // Times the median sell a tiny buy order may be at
+    included_item_groups: Vec<String>, // Category, group or market group names, all if empty
+    excluded_item_groups: Vec<String>, // Category, group or market group names
 }
 
 impl Settings {
@@ -17,9 +63,65 @@ impl Settings {
             update_universe_data: false,
             percentage_treshold: 10.0,
             ship_cargo_volume: 6300.0,
+            ship_profiles: vec![
+                ShipProfile {
+                    name: "tayra".to_string(),
+                    hull: "Tayra".to_string(),
+                    cargo_bonuses: vec![27.5, 27.5, 27.5, 15.0, 15.0, 15.0],
+                    skill_bonus: 5.0,
+                    skill_level: 4,
+                    use_fleet_hangar: false,
+                    use_ore_hold: false,
+                },
+                ShipProfile {
+                    name: "miasmos".to_string(),
+                    hull: "Miasmos".to_string(),
+                    cargo_bonuses: Vec::new(),
+                    skill_bonus: 5.0,
+                    skill_level: 4,
+                    use_fleet_hangar: false,
+                    use_ore_hold: true,
+                },
+            ],
+            active_ship_profile: None,
             max_jumps: 100,
             initial_capital: 50000000.0,
             security_treshold: -1.0,
+            path_mode: PathMode::Safer,
+            accounting_level: 0,
+            broker_relations_level: 0,
+            faction_standing: 0.0,
+            corporation_standing: 0.0,
+            structure_broker_fees: HashMap::new(),
+            buy_with_orders: false,
+            sell_with_orders: false,
+            seconds_per_jump: 60,
+            output_mode: OutputMode::Routes,
+            hauling_sort: HaulingSort::Profit,
+            hauling_limit: 50,
+            min_profit: 0.0,
+            buy_locations: LocationFilter::default(),
+            sell_locations: LocationFilter::default(),
+            start_system: None,
+            end_system: None,
+            round_trip: false,
+            direction_limit: None,
+            alternative_paths: 0,
+            avoided_systems: Vec::new(),
+            avoided_constellations: Vec::new(),
+            avoided_regions: Vec::new(),
+            custom_edges_path: "custom_edges.yaml".to_string(),
+            jump_drive: None,
+            fuel_profile: None,
+            history_days: 0,
+            max_daily_volume_share: None,
+            min_daily_volume: 0.0,
+            outlier_policy: OutlierPolicy::Flag,
+            outlier_price_ratio: 3.0,
+            outlier_tiny_volume: 5.0,
+            outlier_tiny_price_ratio: 1.5,
+            included_item_groups: Vec::new(),
+            excluded_item_groups: Vec::new(),
         }
     }
 
@@ -43,13 +145,174 @@ impl Settings {
         self.ship_cargo_volume
     }
 
+    pub fn get_ship_profiles(&self) -> &Vec<ShipProfile> {
+        &self.ship_profiles
+    }
+
+    pub fn get_active_ship_profile(&self) -> Option<&str> {
+        self.active_ship_profile.as_deref()
+    }
+
     pub fn get_initial_capital(&self) -> f32 {
         self.initial_capital
     }
 
+    pub fn get_security_treshold(&self) -> f32 {
+        self.security_treshold
+    }
+
+    pub fn get_path_mode(&self) -> PathMode {
+        self.path_mode
+    }
+
+    pub fn get_accounting_level(&self) -> u8 {
+        self.accounting_level
+    }
+
+    pub fn get_broker_relations_level(&self) -> u8 {
+        self.broker_relations_level
+    }
+
+    pub fn get_faction_standing(&self) -> f32 {
+        self.faction_standing
+    }
+
+    pub fn get_corporation_standing(&self) -> f32 {
+        self.corporation_standing
+    }
+
+    pub fn get_structure_broker_fees(&self) -> &HashMap<u32, f32> {
+        &self.structure_broker_fees
+    }
+
+    pub fn get_buy_with_orders(&self) -> bool {
+        self.buy_with_orders
+    }
+
+    pub fn get_sell_with_orders(&self) -> bool {
+        self.sell_with_orders
+    }
+
+    pub fn get_seconds_per_jump(&self) -> u32 {
+        self.seconds_per_jump
+    }
+
+    pub fn get_output_mode(&self) -> OutputMode {
+        self.output_mode
+    }
+
+    pub fn get_hauling_sort(&self) -> HaulingSort {
+        self.hauling_sort
+    }
+
+    pub fn get_hauling_limit(&self) -> usize {
+        self.hauling_limit
+    }
+
+    pub fn get_min_profit(&self) -> f32 {
+        self.min_profit
+    }
+
+    pub fn get_buy_locations(&self) -> &LocationFilter {
+        &self.buy_locations
+    }
+
+    pub fn get_sell_locations(&self) -> &LocationFilter {
+        &self.sell_locations
+    }
+
+    pub fn get_start_system(&self) -> Option<u32> {
+        self.start_system
+    }
+
+    pub fn get_end_system(&self) -> Option<u32> {
+        self.end_system
+    }
+
+    pub fn get_round_trip(&self) -> bool {
+        self.round_trip
+    }
+
+    pub fn get_direction_limit(&self) -> Option<usize> {
+        self.direction_limit
+    }
+
+    pub fn get_alternative_paths(&self) -> usize {
+        self.alternative_paths
+    }
+
+    pub fn get_avoided_systems(&self) -> &Vec<String> {
+        &self.avoided_systems
+    }
+
+    pub fn get_avoided_constellations(&self) -> &Vec<u32> {
+        &self.avoided_constellations
+    }
+
+    pub fn get_avoided_regions(&self) -> &Vec<u32> {
+        &self.avoided_regions
+    }
+
+    pub fn get_custom_edges_path(&self) -> &str {
+        &self.custom_edges_path
+    }
+
+    pub fn get_jump_drive(&self) -> Option<JumpDrive> {
+        self.jump_drive
+    }
+
+    pub fn get_fuel_profile(&self) -> Option<FuelProfile> {
+        self.fuel_profile
+    }
+
+    pub fn get_history_days(&self) -> u32 {
+        self.history_days
+    }
+
+    pub fn get_max_daily_volume_share(&self) -> Option<f32> {
+        self.max_daily_volume_share
+    }
+
+    pub fn get_min_daily_volume(&self) -> f32 {
+        self.min_daily_volume
+    }
+
+    pub fn get_outlier_policy(&self) -> OutlierPolicy {
+        self.outlier_policy
+    }
+
+    pub fn get_outlier_price_ratio(&self) -> f32 {
+        self.outlier_price_ratio
+    }
+
+    pub fn get_outlier_tiny_volume(&self) -> f32 {
+        self.outlier_tiny_volume
+    }
+
+    pub fn get_outlier_tiny_price_ratio(&self) -> f32 {
+        self.outlier_tiny_price_ratio
+    }
+
+    pub fn get_included_item_groups(&self) -> &Vec<String> {
+        &self.included_item_groups
+    }
+
+    pub fn get_excluded_item_groups(&self) -> &Vec<String> {
+        &self.excluded_item_groups
+    }
+
+    #[allow(dead_code)]
     pub fn set_level(&mut self, value: log::Level) {
         self.log_level = value;
     }
+
+    pub fn set_active_ship_profile(&mut self, value: String) {
+        self.active_ship_profile = Some(value);
+    }
+
+    pub fn set_output_mode(&mut self, value: OutputMode) {
+        self.output_mode = value;
+    }
 }
 
 lazy_static! {
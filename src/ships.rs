@@ -0,0 +1,128 @@
+use log::{error, info, warn};
+use std::collections::HashMap;
+
+use crate::settings::Settings;
+use crate::types::Type;
+
+pub const SHIP_CATEGORY_ID: u32 = 6;
+pub const ASTEROID_CATEGORY_ID: u32 = 25; // Ore and ice, the only things an ore hold takes
+pub const CAPACITY_ATTRIBUTE_ID: u32 = 38;
+pub const FLEET_HANGAR_CAPACITY_ATTRIBUTE_ID: u32 = 912;
+pub const ORE_HOLD_CAPACITY_ATTRIBUTE_ID: u32 = 1556;
+
+// Unfitted holds of a hull, in m3, straight from its dogma attributes
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, Default)]
+pub struct ShipHolds {
+    pub cargo: f32,
+    pub fleet_hangar: f32,
+    pub ore_hold: f32,
+}
+
+// A ship as we fly it. Expanders and rigs stack without penalty, so every bonus multiplies.
+#[derive(Clone, Debug)]
+pub struct ShipProfile {
+    pub name: String,
+    pub hull: String,            // Type name or id
+    pub cargo_bonuses: Vec<f32>, // Percent each, from expanders and rigs
+    pub skill_bonus: f32,        // Percent cargo per level of the hull skill
+    pub skill_level: u8,
+    pub use_fleet_hangar: bool,
+    pub use_ore_hold: bool,
+}
+
+// What one trip can carry, in m3
+#[derive(Clone, Copy, Debug)]
+pub struct ShipCapacity {
+    pub cargo: f32,    // Cargo hold plus fleet hangar, takes anything
+    pub ore_hold: f32, // Only takes ore and ice
+}
+
+impl ShipCapacity {
+    pub fn get_total(&self) -> f32 {
+        self.cargo + self.ore_hold
+    }
+}
+
+impl ShipProfile {
+    pub fn get_capacity(&self, holds: &ShipHolds) -> ShipCapacity {
+        let skill_multiplier = 1.0 + self.skill_bonus * self.skill_level.min(5) as f32 / 100.0;
+        let cargo = self
+            .cargo_bonuses
+            .iter()
+            .fold(holds.cargo * skill_multiplier, |cargo, bonus| {
+                cargo * (1.0 + bonus / 100.0)
+            });
+
+        let fleet_hangar = if self.use_fleet_hangar {
+            holds.fleet_hangar
+        } else {
+            0.0
+        };
+        let ore_hold = if self.use_ore_hold {
+            holds.ore_hold
+        } else {
+            0.0
+        };
+
+        ShipCapacity {
+            cargo: cargo + fleet_hangar,
+            ore_hold,
+        }
+    }
+}
+
+// The active profile's capacity, or the plain cargo volume setting without one
+pub fn resolve_capacity(
+    settings: &Settings,
+    types: &HashMap<u32, Type>,
+    ship_holds: &HashMap<u32, ShipHolds>,
+) -> ShipCapacity {
+    let fallback = ShipCapacity {
+        cargo: settings.get_ship_cargo_volume(),
+        ore_hold: 0.0,
+    };
+
+    let name = match settings.get_active_ship_profile() {
+        Some(name) => name,
+        None => return fallback,
+    };
+    let profile = match settings
+        .get_ship_profiles()
+        .iter()
+        .find(|profile| profile.name.eq_ignore_ascii_case(name))
+    {
+        Some(profile) => profile,
+        None => {
+            error!("Unknown ship profile: {}", name);
+            return fallback;
+        }
+    };
+
+    let hull_id = profile.hull.parse::<u32>().ok().or_else(|| {
+        types
+            .values()
+            .find(|item_type| {
+                ship_holds.contains_key(&item_type.type_id)
+                    && item_type.name.eq_ignore_ascii_case(&profile.hull)
+            })
+            .map(|item_type| item_type.type_id)
+    });
+    let holds = match hull_id.and_then(|hull_id| ship_holds.get(&hull_id)) {
+        Some(holds) => holds,
+        None => {
+            warn!(
+                "Unknown hull {} of ship profile {}",
+                profile.hull, profile.name
+            );
+            return fallback;
+        }
+    };
+
+    let capacity = profile.get_capacity(holds);
+    info!(
+        "Flying {} ({}): {:.0} m3 cargo, {:.0} m3 ore hold",
+        profile.name, profile.hull, capacity.cargo, capacity.ore_hold
+    );
+
+    capacity
+}
@@ -0,0 +1,513 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::fees::FeeModel;
+use crate::ships::ShipCapacity;
+use crate::types::{Order, Type};
+
+pub fn get_profit_percentage(sell_price: f32, buy_price: f32) -> f32 {
+    (buy_price - sell_price) / sell_price * 100.0
+}
+
+// Order books of one type on both ends of a leg, and when we get to each end.
+// Sell orders must be sorted ascending and buy orders descending by price after fees.
+pub struct TradeBooks<'a> {
+    pub item_type: &'a Type,
+    pub sell_orders: &'a [&'a Order],
+    pub buy_orders: &'a [&'a Order],
+    pub purchase_time: i64, // Unix timestamp
+    pub sale_time: i64,     // Unix timestamp
+    pub max_units: f32,     // Most the destination market can take, infinite if unknown
+    pub fits_ore_hold: bool,
+    pub taken: &'a HashMap<u64, f32>, // Units earlier legs already traded, key: order id
+}
+
+impl TradeBooks<'_> {
+    // What earlier legs of the route left of the order
+    pub fn get_volume_left(&self, order: &Order) -> f32 {
+        let taken = self.taken.get(&order.order_id).copied().unwrap_or(0.0);
+        (order.volume - taken).max(0.0)
+    }
+
+    // Smallest amount a single sale into this buy order may be
+    pub fn get_min_fill(&self, order: &Order) -> f32 {
+        order.min_volume.min(self.get_volume_left(order))
+    }
+}
+
+// Result of packing one cargo load between two systems.
+// Orders are copies of the ones touched, with volume set to the units traded against each.
+// Cost and revenue already include fees.
+pub struct Trade {
+    pub sell_orders: Vec<Order>, // Orders we buy from
+    pub buy_orders: Vec<Order>,  // Orders we sell into
+    pub cost: f32,
+    pub revenue: f32,
+}
+
+impl Trade {
+    pub fn get_profit(&self) -> f32 {
+        self.revenue - self.cost
+    }
+}
+
+// Units taken from every order of every books, indexed like the books themselves
+struct Allocation {
+    sell_taken: Vec<Vec<f32>>,
+    buy_taken: Vec<Vec<f32>>,
+    cost: f32,
+    revenue: f32,
+}
+
+// Units available between one sell order and one buy order at a fixed margin
+struct Segment {
+    books_index: usize,
+    sell_index: usize,
+    buy_index: usize,
+    units: f32,
+    buy_price: f32,
+    sell_price: f32,
+    volume: f32,
+    fits_ore_hold: bool,
+}
+
+pub struct TradeSizer {
+    capacity: ShipCapacity,
+    percentage_treshold: f32,
+    fees: FeeModel,
+}
+
+impl TradeSizer {
+    pub fn new(capacity: ShipCapacity, percentage_treshold: f32, fees: FeeModel) -> Self {
+        TradeSizer {
+            capacity,
+            percentage_treshold,
+            fees,
+        }
+    }
+
+    // Most profitable load of the types that can be hauled on this leg.
+    // Ranking by profit per share of cargo or capital isn't an exact knapsack, bulky units can
+    // leave the hold half empty. A load of a single type is tried too and the better one kept.
+    pub fn pack(&self, books: &[TradeBooks], capital: f32) -> Option<Trade> {
+        let mut best = self.pack_types(books, capital, None);
+        if books.len() < 2 {
+            return best;
+        }
+
+        for (i, single) in books.iter().enumerate() {
+            let best_profit = best.as_ref().map_or(0.0, |trade| trade.get_profit());
+            // Cheap bound, what the books hold before cargo and capital are taken into account
+            let most_profit: f32 = self
+                .walk_books(i, single, &HashSet::new())
+                .iter()
+                .map(|segment| segment.units * (segment.sell_price - segment.buy_price))
+                .sum();
+            if most_profit <= best_profit {
+                continue;
+            }
+
+            if let Some(trade) = self.pack_types(books, capital, Some(i)) {
+                if trade.get_profit() > best_profit {
+                    best = Some(trade);
+                }
+            }
+        }
+
+        best
+    }
+
+    // Greedy fill of every type, or only the one at the given books index.
+    // Buy orders that would be filled below their minimum volume are left out and the load is
+    // packed again, until every fill is acceptable.
+    fn pack_types(&self, books: &[TradeBooks], capital: f32, only: Option<usize>) -> Option<Trade> {
+        let mut excluded: HashSet<(usize, usize)> = HashSet::new(); // Books and buy order index
+
+        loop {
+            let allocation = self.allocate(books, capital, only, &excluded);
+            if allocation.cost <= 0.0 {
+                return None;
+            }
+
+            let mut under_filled = Vec::new();
+            for (i, taken) in allocation.buy_taken.iter().enumerate() {
+                for (k, &units) in taken.iter().enumerate() {
+                    if units > 0.0 && units < books[i].get_min_fill(books[i].buy_orders[k]) {
+                        under_filled.push((i, k));
+                    }
+                }
+            }
+
+            if under_filled.is_empty() {
+                return Some(Self::build_trade(books, allocation));
+            }
+
+            excluded.extend(under_filled);
+        }
+    }
+
+    // Each type's books are cut into segments of constant margin, which are taken in order of
+    // profit per share of the scarcer resource (cargo or capital) until either runs out.
+    // Margins only shrink deeper into the books, so a type's segments are always taken in order.
+    fn allocate(
+        &self,
+        books: &[TradeBooks],
+        capital: f32,
+        only: Option<usize>,
+        excluded: &HashSet<(usize, usize)>,
+    ) -> Allocation {
+        let mut segments: Vec<Segment> = books
+            .iter()
+            .enumerate()
+            .filter(|(books_index, _)| only.is_none_or(|only| only == *books_index))
+            .flat_map(|(books_index, books)| self.walk_books(books_index, books, excluded))
+            .collect();
+
+        segments.sort_by(|a, b| {
+            self.get_score(b, capital)
+                .total_cmp(&self.get_score(a, capital))
+        });
+
+        let mut allocation = Allocation {
+            sell_taken: books
+                .iter()
+                .map(|books| vec![0.0; books.sell_orders.len()])
+                .collect(),
+            buy_taken: books
+                .iter()
+                .map(|books| vec![0.0; books.buy_orders.len()])
+                .collect(),
+            cost: 0.0,
+            revenue: 0.0,
+        };
+        let mut remaining_volume = self.capacity.cargo;
+        let mut remaining_ore_hold = self.capacity.ore_hold;
+        let mut remaining_capital = capital;
+
+        for segment in &segments {
+            let units = segment
+                .units
+                .min(
+                    (self.get_room(segment, remaining_volume, remaining_ore_hold) / segment.volume)
+                        .floor(),
+                )
+                .min((remaining_capital / segment.buy_price).floor());
+            if units <= 0.0 {
+                continue;
+            }
+
+            allocation.sell_taken[segment.books_index][segment.sell_index] += units;
+            allocation.buy_taken[segment.books_index][segment.buy_index] += units;
+            // Ore fills the ore hold first, so the cargo hold stays free for everything else
+            let mut volume = units * segment.volume;
+            if segment.fits_ore_hold {
+                let ore_hold_volume = volume.min(remaining_ore_hold);
+                remaining_ore_hold -= ore_hold_volume;
+                volume -= ore_hold_volume;
+            }
+            remaining_volume -= volume;
+            remaining_capital -= units * segment.buy_price;
+            allocation.cost += units * segment.buy_price;
+            allocation.revenue += units * segment.sell_price;
+        }
+
+        allocation
+    }
+
+    fn build_trade(books: &[TradeBooks], allocation: Allocation) -> Trade {
+        let mut trade = Trade {
+            sell_orders: Vec::new(),
+            buy_orders: Vec::new(),
+            cost: allocation.cost,
+            revenue: allocation.revenue,
+        };
+
+        for (i, books) in books.iter().enumerate() {
+            trade.sell_orders.extend(Self::collect_fills(
+                books.sell_orders,
+                &allocation.sell_taken[i],
+            ));
+            trade.buy_orders.extend(Self::collect_fills(
+                books.buy_orders,
+                &allocation.buy_taken[i],
+            ));
+        }
+
+        trade
+    }
+
+    // Walks both books until the next unit misses the percentage threshold.
+    // Expired and excluded orders are treated as already filled, others only have what earlier
+    // legs left of them.
+    fn walk_books(
+        &self,
+        books_index: usize,
+        books: &TradeBooks,
+        excluded: &HashSet<(usize, usize)>,
+    ) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        let mut sell_left: Vec<f32> = books
+            .sell_orders
+            .iter()
+            .map(|order| {
+                if order.is_expired_at(books.purchase_time) {
+                    0.0
+                } else {
+                    books.get_volume_left(order)
+                }
+            })
+            .collect();
+        let mut buy_left: Vec<f32> = books
+            .buy_orders
+            .iter()
+            .enumerate()
+            .map(|(k, order)| {
+                if order.is_expired_at(books.sale_time) || excluded.contains(&(books_index, k)) {
+                    0.0
+                } else {
+                    books.get_volume_left(order)
+                }
+            })
+            .collect();
+
+        let mut units_left = books.max_units;
+        let (mut i, mut k) = (0, 0);
+        while i < sell_left.len() && k < buy_left.len() && units_left > 0.0 {
+            let sell_order = books.sell_orders[i];
+            let buy_order = books.buy_orders[k];
+
+            if self.fees.get_profit_percentage(sell_order, buy_order) < self.percentage_treshold {
+                break;
+            }
+
+            let units = sell_left[i].min(buy_left[k]).min(units_left);
+            if units > 0.0 {
+                segments.push(Segment {
+                    books_index,
+                    sell_index: i,
+                    buy_index: k,
+                    units,
+                    buy_price: self.fees.get_net_buy_price(sell_order),
+                    sell_price: self.fees.get_net_sell_price(buy_order),
+                    volume: books.item_type.volume,
+                    fits_ore_hold: books.fits_ore_hold,
+                });
+            }
+
+            sell_left[i] -= units;
+            buy_left[k] -= units;
+            units_left -= units;
+            if sell_left[i] <= 0.0 {
+                i += 1;
+            }
+            if buy_left[k] <= 0.0 {
+                k += 1;
+            }
+        }
+
+        segments
+    }
+
+    fn get_score(&self, segment: &Segment, capital: f32) -> f32 {
+        let cargo_share =
+            segment.volume / self.get_room(segment, self.capacity.cargo, self.capacity.ore_hold);
+        let capital_share = segment.buy_price / capital;
+
+        (segment.sell_price - segment.buy_price) / cargo_share.max(capital_share)
+    }
+
+    fn get_room(&self, segment: &Segment, cargo: f32, ore_hold: f32) -> f32 {
+        if segment.fits_ore_hold {
+            cargo + ore_hold
+        } else {
+            cargo
+        }
+    }
+
+    fn collect_fills(orders: &[&Order], taken: &[f32]) -> Vec<Order> {
+        orders
+            .iter()
+            .zip(taken)
+            .filter(|(_, &units)| units > 0.0)
+            .map(|(order, &units)| Order {
+                volume: units,
+                ..(*order).clone()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::OrderRange;
+
+    fn item_type(type_id: u32, volume: f32) -> Type {
+        Type {
+            type_id,
+            group_id: 1,
+            market_group_id: None,
+            name: format!("Type {}", type_id),
+            volume,
+        }
+    }
+
+    fn order(order_id: u64, item_type: &Type, price: f32, volume: f32, is_buy: bool) -> Order {
+        Order {
+            order_id,
+            is_buy_order: is_buy,
+            order_type: item_type.clone(),
+            price,
+            station_id: 60000001,
+            system_id: 30000001,
+            region_id: 10000001,
+            volume,
+            min_volume: 1.0,
+            range: OrderRange::Station,
+            issued: 0,
+            duration: 90,
+            outlier: None,
+        }
+    }
+
+    fn books<'a>(
+        item_type: &'a Type,
+        sell_orders: &'a [&'a Order],
+        buy_orders: &'a [&'a Order],
+        taken: &'a HashMap<u64, f32>,
+    ) -> TradeBooks<'a> {
+        TradeBooks {
+            item_type,
+            sell_orders,
+            buy_orders,
+            purchase_time: 0,
+            sale_time: 0,
+            max_units: f32::INFINITY,
+            fits_ore_hold: false,
+            taken,
+        }
+    }
+
+    fn sizer(cargo: f32) -> TradeSizer {
+        let capacity = ShipCapacity {
+            cargo,
+            ore_hold: 0.0,
+        };
+        TradeSizer::new(capacity, 0.0, FeeModel::default())
+    }
+
+    // One unit of the denser type leaves no room for a second one of anything
+    #[test]
+    fn single_type_beats_denser_mix() {
+        let bulky = item_type(1, 6.0);
+        let compact = item_type(2, 5.0);
+        let bulky_sell = order(1, &bulky, 10.0, 100.0, false);
+        let bulky_buy = order(2, &bulky, 17.0, 100.0, true);
+        let compact_sell = order(3, &compact, 10.0, 100.0, false);
+        let compact_buy = order(4, &compact, 15.0, 100.0, true);
+        let taken = HashMap::new();
+        let (bulky_sells, bulky_buys) = ([&bulky_sell], [&bulky_buy]);
+        let (compact_sells, compact_buys) = ([&compact_sell], [&compact_buy]);
+
+        let trade = sizer(10.0)
+            .pack(
+                &[
+                    books(&bulky, &bulky_sells, &bulky_buys, &taken),
+                    books(&compact, &compact_sells, &compact_buys, &taken),
+                ],
+                1_000_000.0,
+            )
+            .unwrap();
+
+        assert_eq!(trade.get_profit(), 10.0);
+        assert_eq!(trade.buy_orders.len(), 1);
+        assert_eq!(trade.buy_orders[0].order_id, 4);
+        assert_eq!(trade.buy_orders[0].volume, 2.0);
+    }
+
+    // Deeper sell orders are taken once the cheapest one runs out, the buy order caps the load
+    #[test]
+    fn walks_several_price_levels() {
+        let item = item_type(1, 1.0);
+        let cheap = order(1, &item, 10.0, 5.0, false);
+        let dearer = order(2, &item, 11.0, 5.0, false);
+        let buy = order(3, &item, 20.0, 8.0, true);
+        let taken = HashMap::new();
+        let (sells, buys) = ([&cheap, &dearer], [&buy]);
+
+        let trade = sizer(1000.0)
+            .pack(&[books(&item, &sells, &buys, &taken)], 1_000_000.0)
+            .unwrap();
+
+        let bought: Vec<(u64, f32)> = trade
+            .sell_orders
+            .iter()
+            .map(|order| (order.order_id, order.volume))
+            .collect();
+        assert_eq!(bought, vec![(1, 5.0), (2, 3.0)]);
+        assert_eq!(trade.cost, 83.0);
+        assert_eq!(trade.revenue, 160.0);
+    }
+
+    // Five units can't fill the best buy order, so the load goes to the next one instead
+    #[test]
+    fn repacks_without_under_filled_buy_orders() {
+        let item = item_type(1, 1.0);
+        let sell = order(1, &item, 10.0, 5.0, false);
+        let big_buyer = Order {
+            min_volume: 10.0,
+            ..order(2, &item, 30.0, 20.0, true)
+        };
+        let small_buyer = order(3, &item, 20.0, 20.0, true);
+        let taken = HashMap::new();
+        let (sells, buys) = ([&sell], [&big_buyer, &small_buyer]);
+
+        let trade = sizer(1000.0)
+            .pack(&[books(&item, &sells, &buys, &taken)], 1_000_000.0)
+            .unwrap();
+
+        assert_eq!(trade.buy_orders.len(), 1);
+        assert_eq!(trade.buy_orders[0].order_id, 3);
+        assert_eq!(trade.buy_orders[0].volume, 5.0);
+    }
+
+    // The best buy order is still up when we buy but gone by the time we get there
+    #[test]
+    fn skips_orders_expired_on_arrival() {
+        let item = item_type(1, 1.0);
+        let sell = order(1, &item, 10.0, 5.0, false);
+        let expiring = Order {
+            issued: 1,
+            duration: 1,
+            ..order(2, &item, 30.0, 5.0, true)
+        };
+        let lasting = order(3, &item, 20.0, 5.0, true);
+        let taken = HashMap::new();
+        let (sells, buys) = ([&sell], [&expiring, &lasting]);
+        let leg = TradeBooks {
+            sale_time: expiring.get_expiry() + 1,
+            ..books(&item, &sells, &buys, &taken)
+        };
+
+        let trade = sizer(1000.0).pack(&[leg], 1_000_000.0).unwrap();
+
+        assert_eq!(trade.buy_orders.len(), 1);
+        assert_eq!(trade.buy_orders[0].order_id, 3);
+    }
+
+    // Earlier legs of the route already bought most of the sell order
+    #[test]
+    fn leaves_out_what_earlier_legs_took() {
+        let item = item_type(1, 1.0);
+        let sell = order(1, &item, 10.0, 10.0, false);
+        let buy = order(2, &item, 20.0, 100.0, true);
+        let taken = HashMap::from([(sell.order_id, 6.0)]);
+        let (sells, buys) = ([&sell], [&buy]);
+
+        let trade = sizer(1000.0)
+            .pack(&[books(&item, &sells, &buys, &taken)], 1_000_000.0)
+            .unwrap();
+
+        assert_eq!(trade.sell_orders.len(), 1);
+        assert_eq!(trade.sell_orders[0].volume, 4.0);
+    }
+}
@@ -1,4 +1,7 @@
-use serde;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use crate::outliers::OutlierReason;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
 pub struct Vector3 {
@@ -7,11 +10,39 @@ pub struct Vector3 {
     pub z: f64,
 }
 
+// Stargates come from the universe data, everything else from the user's custom edges
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StargateKind {
+    Stargate,
+    Wormhole,
+    JumpBridge,
+}
+
+impl std::fmt::Display for StargateKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StargateKind::Stargate => write!(f, "stargate"),
+            StargateKind::Wormhole => write!(f, "wormhole"),
+            StargateKind::JumpBridge => write!(f, "jump bridge"),
+        }
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
 pub struct Stargate {
     pub origin: u32,
     pub destination: u32,
     pub weight: f32,
+    pub kind: StargateKind,
+}
+
+impl Stargate {
+    // From 1 for a highsec destination to 10 for a nullsec one
+    pub fn get_weight(destination_security: f32) -> f32 {
+        let weight = 1.0 + ((destination_security - (-1.0)) * (10.0 - 1.0) / (1.0 - (-1.0)));
+        ((10.0 + 1.0) - weight).ceil()
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Clone)]
@@ -21,10 +52,43 @@ pub struct System {
     pub security_status: f32,
     pub stargates: Vec<Stargate>,
     pub position: Vector3,
+    pub constellation_id: u32,
+    pub region_id: u32,
+}
+
+impl System {
+    // Systems can be referred to by id or by name, in any case
+    pub fn find_id(systems: &HashMap<u32, System>, id_or_name: &str) -> Option<u32> {
+        match id_or_name.trim().parse::<u32>() {
+            Ok(system_id) if systems.contains_key(&system_id) => Some(system_id),
+            _ => systems
+                .values()
+                .find(|system| system.name.eq_ignore_ascii_case(id_or_name.trim()))
+                .map(|system| system.id),
+        }
+    }
+
+    // How we get from here to the destination, if they are connected at all
+    pub fn get_connection_kind(&self, destination: u32) -> Option<StargateKind> {
+        self.stargates
+            .iter()
+            .find(|stargate| stargate.destination == destination)
+            .map(|stargate| stargate.kind)
+    }
+
+    // Security as shown in game, which is what highsec/lowsec boundaries are based on
+    pub fn get_display_security(&self) -> f32 {
+        if self.security_status > 0.0 && self.security_status < 0.05 {
+            return 0.1;
+        }
+
+        (self.security_status * 10.0).round() / 10.0
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct Order {
+    pub order_id: u64,
     pub is_buy_order: bool,
     pub order_type: Type,
     pub price: f32,
@@ -32,6 +96,56 @@ pub struct Order {
     pub system_id: u32,
     pub region_id: u32,
     pub volume: f32,
+    pub min_volume: f32,
+    pub range: OrderRange,
+    pub issued: i64,   // Unix timestamp
+    pub duration: u32, // Days
+    #[serde(skip)]
+    pub outlier: Option<OutlierReason>, // Set in preprocessing for suspicious buy orders
+}
+
+impl Order {
+    pub fn get_expiry(&self) -> i64 {
+        self.issued + self.duration as i64 * 24 * 60 * 60
+    }
+
+    // Orders with an unknown issue date are assumed to stay up
+    pub fn is_expired_at(&self, time: i64) -> bool {
+        self.issued != 0 && self.get_expiry() < time
+    }
+}
+
+// Where a buy order can be filled from, sell orders are always station bound
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrderRange {
+    Station,
+    SolarSystem,
+    Jumps(u8),
+    Region,
+}
+
+impl OrderRange {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "region" => OrderRange::Region,
+            "solarsystem" => OrderRange::SolarSystem,
+            _ => value
+                .parse()
+                .map(OrderRange::Jumps)
+                .unwrap_or(OrderRange::Station),
+        }
+    }
+}
+
+impl std::fmt::Display for OrderRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderRange::Station => write!(f, "station"),
+            OrderRange::SolarSystem => write!(f, "solar system"),
+            OrderRange::Jumps(jumps) => write!(f, "{} jumps", jumps),
+            OrderRange::Region => write!(f, "region"),
+        }
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Clone, Default)]
@@ -41,10 +155,6 @@ pub struct OrderGroup {
 }
 
 impl OrderGroup {
-    pub fn new() -> Self {
-        Default::default()
-    }
-
     pub fn add_order(&mut self, order: Order) {
         if order.is_buy_order {
             self.buy.push(order);
@@ -68,22 +178,54 @@ impl OrderGroup {
 //     pub orders: std::collections::HashMap<i32, OrderGroup>, // Key: type id
 // }
 
+// Restricts trading to some regions, systems or stations. An empty filter allows everything.
+#[derive(Clone, Default, Debug)]
+pub struct LocationFilter {
+    pub regions: Vec<u32>,
+    pub systems: Vec<u32>,
+    pub stations: Vec<u32>,
+}
+
+impl LocationFilter {
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty() && self.systems.is_empty() && self.stations.is_empty()
+    }
+
+    pub fn allows_order(&self, order: &Order) -> bool {
+        self.is_empty()
+            || self.stations.contains(&order.station_id)
+            || self.systems.contains(&order.system_id)
+            || self.regions.contains(&order.region_id)
+    }
+
+    // Any station of the system will do, so a station only filter never matches here
+    pub fn allows_system(&self, system_id: u32, region_id: u32) -> bool {
+        self.is_empty() || self.systems.contains(&system_id) || self.regions.contains(&region_id)
+    }
+}
+
+// Single hop station to station haul, one row of the hauling table
 pub struct TradePair {
-    pub buy_system_id: i32,
-    pub sell_system_id: i32,
-    pub type_id: i32,
+    pub buy_station_id: u32,
+    pub buy_system_id: u32,
+    pub sell_station_id: u32,
+    pub sell_system_id: u32,
+    pub order_type: Type,
     pub potential_profit: f32,
     pub volume: i32,
     pub buy_price: f32,
     pub sell_price: f32,
     pub jumps: i32,
     pub profit_per_jump: f32,
+    pub roi: f32,
+    pub days_to_sell: Option<f32>, // None without market history
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct Type {
     pub type_id: u32,
     pub group_id: u32,
+    pub market_group_id: Option<u32>, // None for types that can't be traded on the market
     pub name: String,
     pub volume: f32,
 }
@@ -94,6 +236,7 @@ pub enum Waypoint {
     Order(Order),
 }
 
+#[allow(dead_code)]
 pub struct State {
     pub priority: f32,
     pub cost: i32,
@@ -128,6 +271,15 @@ impl Vector3 {
         w1 * direction_similarity + w2 * magnitude_similarity
     }
 
+    // Vector pointing from here to other
+    pub fn direction_to(&self, other: &Vector3) -> Vector3 {
+        Vector3 {
+            x: other.x - self.x,
+            y: other.y - self.y,
+            z: other.z - self.z,
+        }
+    }
+
     pub fn distance(&self, other: &Vector3) -> f64 {
         let x = self.x - other.x;
         let y = self.y - other.y;
@@ -137,10 +289,44 @@ impl Vector3 {
     }
 }
 
+#[derive(Clone)]
 pub struct TradeCandidate {
     pub profit_per_jump: f32,
-    pub system_id: i32,
+    pub profit: f32,
+    pub jumps: usize,
+    pub system_id: u32,
     pub capital: f32,
     pub waypoints: Vec<Waypoint>,
-    pub visited: Vec<bool>,
+    pub visited: HashSet<u32>,
+    pub taken: HashMap<u64, f32>, // Units traded against every order so far, key: order id
+    pub origin: u32,              // System the route started in
+    pub is_closed: bool,          // Already back home, can't be extended any further
+}
+
+// Candidates are ordered by profit per jump so that BinaryHeap pops the best one first.
+// Ties are broken on the rest, so that the search doesn't depend on the order of insertion.
+impl PartialEq for TradeCandidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for TradeCandidate {}
+
+impl PartialOrd for TradeCandidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TradeCandidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.profit_per_jump
+            .total_cmp(&other.profit_per_jump)
+            .then_with(|| self.profit.total_cmp(&other.profit))
+            .then_with(|| other.jumps.cmp(&self.jumps))
+            .then_with(|| other.system_id.cmp(&self.system_id))
+            .then_with(|| other.origin.cmp(&self.origin))
+            .then_with(|| self.is_closed.cmp(&other.is_closed))
+    }
 }
@@ -3,6 +3,7 @@ const GATECAMP_URL: &str = "https://eve-gatecheck.space/eve/#";
 const ESI_SCRAPE_URL: &str = "https://data.everef.net/esi-scrape/eve-ref-esi-scrape-latest.tar.xz";
 const MARKET_DATA_URL: &str =
     "https://data.everef.net/market-orders/market-orders-latest.v3.csv.bz2";
+const MARKET_HISTORY_URL: &str = "https://data.everef.net/market-history";
 
 pub fn get_esi_scrape_url() -> String {
     ESI_SCRAPE_URL.to_string()
@@ -12,6 +13,15 @@ pub fn get_market_data_url() -> String {
     MARKET_DATA_URL.to_string()
 }
 
+pub fn get_market_history_url(date: chrono::NaiveDate) -> String {
+    format!(
+        "{}/{}/market-history-{}.csv.bz2",
+        MARKET_HISTORY_URL,
+        date.format("%Y"),
+        date.format("%Y-%m-%d")
+    )
+}
+
 pub fn get_market_browser_url(type_id: u32) -> String {
     format!("{}/{}", MARKET_BROWSER_URL, type_id)
 }